@@ -0,0 +1,84 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::Context,
+};
+
+use awaitdrop::awaitdrop;
+use criterion::{
+    criterion_group,
+    criterion_main,
+    BatchSize,
+    Criterion,
+};
+use futures::task::noop_waker;
+
+fn clone(c: &mut Criterion) {
+    let (task, _wait) = awaitdrop();
+    c.bench_function("clone", |b| b.iter_with_large_drop(|| task.clone()));
+}
+
+fn drop_ref(c: &mut Criterion) {
+    let (task, wait) = awaitdrop();
+    c.bench_function("drop", |b| {
+        b.iter_batched(|| task.clone(), drop, BatchSize::SmallInput)
+    });
+
+    // Watched groups take the slower path through the watch.
+    wait.on_below(0, || {});
+    c.bench_function("drop watched", |b| {
+        b.iter_batched(|| task.clone(), drop, BatchSize::SmallInput)
+    });
+}
+
+fn drop_last(c: &mut Criterion) {
+    c.bench_function("drop last", |b| {
+        b.iter_batched(
+            awaitdrop,
+            |(task, wait)| {
+                drop(task);
+                wait
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn register(c: &mut Criterion) {
+    let (_task, wait) = awaitdrop();
+    let waker = noop_waker();
+    c.bench_function("wait register", |b| {
+        b.iter_batched(
+            || wait.wait(),
+            |mut fut| {
+                let _ = Pin::new(&mut fut).poll(&mut Context::from_waker(&waker));
+                fut
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn wake_all(c: &mut Criterion) {
+    let waker = noop_waker();
+    c.bench_function("wake 64", |b| {
+        b.iter_batched(
+            || {
+                let (task, wait) = awaitdrop();
+                let mut futs = (0..64).map(|_| wait.wait()).collect::<Vec<_>>();
+                for fut in &mut futs {
+                    let _ = Pin::new(fut).poll(&mut Context::from_waker(&waker));
+                }
+                (task, futs)
+            },
+            |(task, futs)| {
+                drop(task);
+                futs
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, clone, drop_ref, drop_last, register, wake_all);
+criterion_main!(benches);
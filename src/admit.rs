@@ -0,0 +1,136 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+};
+
+use futures::Sink;
+
+use crate::{
+    AcquireFuture,
+    Guard,
+    Ref,
+    Waiter,
+};
+
+impl Waiter {
+    /// Wrap `sink` so that every item sent through it is tracked by a new
+    /// [Ref] in this group
+    ///
+    /// Each item is sent as a [Guard] holding one of the group's [Ref]s, and
+    /// [Sink::poll_ready] waits for room under the group's
+    /// [quota](Waiter::set_quota), just like [Waiter::acquire]. That turns the
+    /// quota into backpressure for a pipeline: at most that many items can be
+    /// in flight, counting any other [Ref]s the group has.
+    pub fn admit<S>(&self, sink: S) -> Admit<S> {
+        Admit {
+            acquire: self.acquire(),
+            permit: None,
+            sink,
+        }
+    }
+}
+
+/// The error returned by an [Admit] sink
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdmitError<E> {
+    /// Every [Ref] in the group has been dropped, so no more items can be
+    /// admitted
+    Drained,
+    /// The wrapped sink failed
+    Sink(E),
+}
+
+/// The sink returned from [Waiter::admit]
+pub struct Admit<S> {
+    acquire: AcquireFuture,
+    // Acquired by `poll_ready`, used up by `start_send`.
+    permit: Option<Ref>,
+    sink: S,
+}
+
+impl<S> Admit<S> {
+    /// Get the wrapped sink back
+    ///
+    /// A [Ref] acquired for an item that was never sent is released.
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+}
+
+impl<T, S: Sink<Guard<T>> + Unpin> Sink<T> for Admit<S> {
+    type Error = AdmitError<S::Error>;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = &mut *self;
+        if this.permit.is_none() {
+            match futures::ready!(Pin::new(&mut this.acquire).poll(cx)) {
+                Some(permit) => this.permit = Some(permit),
+                None => return Poll::Ready(Err(AdmitError::Drained)),
+            }
+        }
+        Pin::new(&mut this.sink)
+            .poll_ready(cx)
+            .map_err(AdmitError::Sink)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let this = &mut *self;
+        let permit = this
+            .permit
+            .take()
+            .expect("Admit::start_send called without poll_ready");
+        Pin::new(&mut this.sink)
+            .start_send(Guard::new(item, permit))
+            .map_err(AdmitError::Sink)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink)
+            .poll_flush(cx)
+            .map_err(AdmitError::Sink)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink)
+            .poll_close(cx)
+            .map_err(AdmitError::Sink)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{
+        channel::mpsc,
+        FutureExt,
+        SinkExt,
+        StreamExt,
+    };
+
+    use super::AdmitError;
+
+    #[test]
+    fn quota_backpressure() {
+        let (task, wait) = crate::awaitdrop();
+        wait.set_quota(Some(3));
+        let (tx, mut rx) = mpsc::unbounded();
+        let mut sink = wait.admit(tx);
+
+        // The original Ref takes one of the three places.
+        sink.send(1).now_or_never().unwrap().unwrap();
+        sink.send(2).now_or_never().unwrap().unwrap();
+        assert!(sink.send(3).now_or_never().is_none());
+
+        let first = rx.next().now_or_never().unwrap().unwrap();
+        assert_eq!(*first, 1);
+        drop(first);
+        sink.send(3).now_or_never().unwrap().unwrap();
+
+        drop(rx);
+        drop(task);
+        assert_eq!(sink.send(4).now_or_never(), Some(Err(AdmitError::Drained)));
+    }
+}
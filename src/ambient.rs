@@ -0,0 +1,140 @@
+use std::{
+    cell::RefCell,
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{
+        self,
+        Arc,
+    },
+    task::{
+        Context,
+        Poll,
+    },
+};
+
+use crate::{
+    Inner,
+    Ref,
+};
+
+thread_local! {
+    // The group entered on this thread, if any. Weak, since the guard that set
+    // it borrows a Ref that keeps the group alive anyway.
+    static CURRENT: RefCell<Option<sync::Weak<Inner>>> = const { RefCell::new(None) };
+}
+
+impl Ref {
+    /// Make this [Ref]'s group the current one on this thread, until the
+    /// returned guard is dropped
+    ///
+    /// Code further down the call stack can then join the group with
+    /// [Ref::current], without having a [Ref] passed to it. Guards should be
+    /// dropped in the reverse order they were created in.
+    pub fn enter(&self) -> Entered<'_> {
+        let previous =
+            CURRENT.with(|current| current.borrow_mut().replace(Arc::downgrade(self.inner())));
+        Entered {
+            previous,
+            _ref: PhantomData,
+        }
+    }
+
+    /// Get a new [Ref] in the group that was last [entered](Ref::enter) on
+    /// this thread, if any
+    ///
+    /// Like [Clone], this always succeeds while a group is entered, even over
+    /// its [quota](crate::Waiter::set_quota).
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn current() -> Option<Ref> {
+        let inner = CURRENT.with(|current| current.borrow().as_ref()?.upgrade())?;
+        Some(Ref::created(inner))
+    }
+
+    /// Enter this [Ref]'s group every time `fut` is polled
+    ///
+    /// Since tasks can move between threads at any await point, this is how to
+    /// make [Ref::current] work inside async code. The [Ref] is held until the
+    /// returned future is dropped.
+    pub fn instrument<F: Future>(self, fut: F) -> Instrumented<F> {
+        Instrumented {
+            tracker: self,
+            fut: Box::pin(fut),
+        }
+    }
+}
+
+/// The guard returned from [Ref::enter]
+///
+/// Restores the previously entered group, if any, when dropped.
+pub struct Entered<'a> {
+    previous: Option<sync::Weak<Inner>>,
+    // Borrow the Ref to keep the group alive, and stay on this thread.
+    _ref: PhantomData<(&'a Ref, *const ())>,
+}
+
+impl Drop for Entered<'_> {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// The future returned from [Ref::instrument]
+pub struct Instrumented<F> {
+    tracker: Ref,
+    fut: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for Instrumented<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = &mut *self;
+        let _entered = this.tracker.enter();
+        this.fut.as_mut().poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::executor::block_on;
+
+    use crate::Ref;
+
+    #[test]
+    fn enter_current() {
+        assert!(Ref::current().is_none());
+
+        let (task, wait) = crate::awaitdrop();
+        let (other, _other_wait) = crate::awaitdrop();
+        {
+            let _entered = task.enter();
+            let joined = Ref::current().unwrap();
+            assert_eq!(joined.id().group, task.id().group);
+
+            {
+                let _entered = other.enter();
+                assert_eq!(Ref::current().unwrap().id().group, other.id().group);
+            }
+            assert_eq!(Ref::current().unwrap().id().group, task.id().group);
+            drop(joined);
+        }
+        assert!(Ref::current().is_none());
+        assert_eq!(wait.observer().count(), 1);
+    }
+
+    #[test]
+    fn instrument() {
+        let (task, wait) = crate::awaitdrop();
+        let observer = wait.observer();
+
+        let count = block_on(task.instrument(async move {
+            let _joined = Ref::current().unwrap();
+            observer.count()
+        }));
+        assert_eq!(count, 2);
+        assert!(Ref::current().is_none());
+        block_on(wait.wait());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{
+    awaitdrop,
+    Ref,
+    WaitFuture,
+    Waiter,
+};
+
+/// The coordinator side of a two-sided barrier created by [barrier].
+///
+/// Can wait both for every worker to start and for every worker to finish.
+#[derive(Clone)]
+pub struct Barrier {
+    started: Waiter,
+    pub(crate) done: Waiter,
+}
+
+impl Barrier {
+    /// Wait for every [BarrierWorker] to have started or been dropped
+    pub fn all_started(&self) -> WaitFuture {
+        self.started.wait()
+    }
+
+    /// Wait for every [BarrierWorker], and every [Ref] cloned from them, to
+    /// be dropped
+    pub fn all_done(&self) -> WaitFuture {
+        self.done.wait()
+    }
+}
+
+/// The worker side of a two-sided barrier created by [barrier].
+///
+/// Holds a [Ref] that keeps [Barrier::all_done] pending until it's dropped.
+/// Dropping a worker that was never started also counts as starting it, so a
+/// worker that exits early can't hang everyone else.
+pub struct BarrierWorker {
+    start: Option<Ref>,
+    started: Waiter,
+    done: Ref,
+}
+
+impl BarrierWorker {
+    /// Mark this worker as started
+    pub fn start(&mut self) {
+        self.start.take();
+    }
+
+    /// Mark this worker as started and wait for all of the others
+    pub fn all_started(&mut self) -> WaitFuture {
+        self.start();
+        self.started.wait()
+    }
+
+    /// Get the [Ref] that this worker holds on the completion group
+    pub fn done_ref(&self) -> &Ref {
+        &self.done
+    }
+}
+
+/// Create a two-sided barrier for `workers` workers
+///
+/// Each [BarrierWorker] can wait for every other worker to start, while the
+/// [Barrier] can wait for them to start as well as for them to finish.
+#[cfg_attr(feature = "debug-tracking", track_caller)]
+pub fn barrier(workers: usize) -> (Barrier, Vec<BarrierWorker>) {
+    let (start, started) = awaitdrop();
+    let (done, finished) = awaitdrop();
+
+    // A loop rather than a map, since closures don't forward the caller's
+    // location to the clones.
+    let mut list = Vec::with_capacity(workers);
+    for _ in 0..workers {
+        list.push(BarrierWorker {
+            start: Some(start.clone()),
+            started: started.clone(),
+            done: done.clone(),
+        });
+    }
+
+    (
+        Barrier {
+            started,
+            done: finished,
+        },
+        list,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use futures::executor::block_on;
+
+    #[test]
+    fn start_then_finish() {
+        let (barrier, workers) = super::barrier(4);
+
+        let handles = workers
+            .into_iter()
+            .map(|mut worker| {
+                thread::spawn(move || {
+                    block_on(worker.all_started());
+                    drop(worker);
+                })
+            })
+            .collect::<Vec<_>>();
+
+        block_on(barrier.all_started());
+        block_on(barrier.all_done());
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn dropped_worker_counts_as_started() {
+        let (barrier, mut workers) = super::barrier(2);
+
+        let mut first = workers.remove(0);
+        drop(workers);
+
+        block_on(first.all_started());
+        block_on(barrier.all_started());
+    }
+
+    #[cfg(feature = "debug-tracking")]
+    #[test]
+    fn worker_locations() {
+        let (_barrier, workers) = super::barrier(2);
+        let line = line!() - 1;
+        for worker in &workers {
+            assert_eq!(worker.done_ref().location().line(), line);
+            assert_eq!(worker.done_ref().location().file(), file!());
+        }
+    }
+}
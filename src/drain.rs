@@ -0,0 +1,316 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{
+        Context,
+        Poll,
+    },
+    thread::{
+        self,
+        Thread,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use futures::task::{
+    self,
+    ArcWake,
+};
+use slotmap::DefaultKey;
+
+use crate::{
+    RefId,
+    WaitFuture,
+    Waiter,
+};
+
+/// A summary of a completed drain, produced by [Waiter::drain](crate::Waiter::drain)
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct DrainReport {
+    /// When the drain started
+    pub started: Instant,
+    /// How long it took for every [Ref](crate::Ref) to be dropped
+    pub elapsed: Duration,
+}
+
+impl Waiter {
+    /// Wait for all connected [Ref](crate::Ref)s to be dropped and report on
+    /// how it went
+    pub fn drain(&self) -> DrainFuture {
+        DrainFuture {
+            started: Instant::now(),
+            wait: self.wait(),
+            clock: None,
+        }
+    }
+
+    /// Like [Waiter::drain], but with the time taken
+    /// from `clock` rather than [Instant::now]
+    ///
+    /// For simulations and deterministic tests, where the time that matters
+    /// is whatever the simulated runtime says it is.
+    pub fn drain_with_clock(
+        &self,
+        clock: impl Fn() -> Instant + Send + Sync + 'static,
+    ) -> DrainFuture {
+        DrainFuture {
+            started: clock(),
+            wait: self.wait(),
+            clock: Some(Box::new(clock)),
+        }
+    }
+
+    /// Block the current thread until every connected [Ref](crate::Ref) has
+    /// been dropped or `timeout` passes, returning whether the group drained
+    ///
+    /// Meant for `Drop` impls that have to make an attempt at draining
+    /// synchronously. It never panics, and the wait is bounded even if the
+    /// group never drains. It doesn't need a runtime, but it does block the
+    /// thread, so calling it from inside an async task stalls that task's
+    /// executor thread for up to `timeout`.
+    pub fn drain_in_drop(&self, timeout: Duration) -> bool {
+        // A timeout too long to represent is as good as none at all.
+        let deadline = Instant::now().checked_add(timeout);
+        let waker = task::waker(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = self.wait();
+
+        loop {
+            if Pin::new(&mut fut).poll(&mut cx).is_ready() {
+                return true;
+            }
+            let deadline = match deadline {
+                Some(deadline) => deadline,
+                None => {
+                    thread::park();
+                    continue;
+                }
+            };
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            thread::park_timeout(deadline - now);
+        }
+    }
+
+    /// Wait for every connected [Ref](crate::Ref) to be dropped, or for the
+    /// first one to be dropped during a panic
+    ///
+    /// In the latter case, the future resolves right away with the id of the
+    /// [Ref](crate::Ref) whose holder crashed, without waiting for the rest.
+    /// A plain [Waiter::wait] on the same group keeps waiting for all of them.
+    pub fn fail_fast(&self) -> FailFast {
+        FailFast {
+            wait: self.wait(),
+            idx: None,
+        }
+    }
+}
+
+struct Unpark(Thread);
+
+impl ArcWake for Unpark {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.unpark();
+    }
+}
+
+/// The future returned from [Waiter::fail_fast]
+///
+/// Besides the slot its inner [WaitFuture] takes, it registers for panics in a
+/// slot of its own, so that a panicking drop only wakes the futures that care.
+pub struct FailFast {
+    wait: WaitFuture,
+    idx: Option<DefaultKey>,
+}
+
+impl Drop for FailFast {
+    fn drop(&mut self) {
+        if let Some(idx) = self.idx {
+            self.wait.waiter.shared.fail_fast_wakers.lock().remove(idx);
+        }
+    }
+}
+
+impl Future for FailFast {
+    type Output = Result<(), RefId>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        // Register first so that a panic is either seen below or wakes us, the
+        // same as the count in `WaitFuture`.
+        this.wait
+            .waiter
+            .shared
+            .fail_fast_wakers
+            .lock()
+            .register(&mut this.idx, cx.waker());
+        let drained = Pin::new(&mut this.wait).poll(cx).is_ready();
+        if let Some(id) = *this.wait.waiter.shared.panicked.lock() {
+            return Poll::Ready(Err(id));
+        }
+        if drained {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// A [Ref](crate::Ref) that outlived its drain deadline, reported by
+/// [Waiter::overdue](crate::Waiter::overdue) once it was dropped
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct OverdueRef {
+    /// The [Ref](crate::Ref) that was late
+    pub id: RefId,
+    /// The deadline it was given with
+    /// [Ref::with_drain_deadline](crate::Ref::with_drain_deadline)
+    pub deadline: Duration,
+    /// How long after the drain request it was actually dropped
+    pub elapsed: Duration,
+}
+
+/// The future returned from [Waiter::drain](crate::Waiter::drain)
+///
+/// Resolves to a [DrainReport] when all connected [Ref](crate::Ref)s have been
+/// dropped.
+pub struct DrainFuture {
+    pub(crate) started: Instant,
+    pub(crate) wait: WaitFuture,
+    // Where to get the time from, if not `Instant::now`.
+    pub(crate) clock: Option<Box<dyn Fn() -> Instant + Send + Sync>>,
+}
+
+impl Future for DrainFuture {
+    type Output = DrainReport;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        futures::ready!(Pin::new(&mut self.wait).poll(cx));
+        let now = self
+            .clock
+            .as_ref()
+            .map_or_else(Instant::now, |clock| clock());
+        Poll::Ready(DrainReport {
+            started: self.started,
+            elapsed: now.saturating_duration_since(self.started),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{
+                AtomicU64,
+                Ordering,
+            },
+            Arc,
+        },
+        thread,
+        time::{
+            Duration,
+            Instant,
+        },
+    };
+
+    use futures::{
+        executor::block_on,
+        FutureExt,
+    };
+
+    #[test]
+    fn report_elapsed() {
+        let (task, wait) = crate::awaitdrop();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(task);
+        });
+
+        let report = block_on(wait.drain());
+
+        assert!(report.elapsed >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn drain_with_clock() {
+        let base = Instant::now();
+        let ticks = Arc::new(AtomicU64::new(0));
+        let clock = {
+            let ticks = ticks.clone();
+            move || base + Duration::from_secs(ticks.load(Ordering::SeqCst))
+        };
+
+        let (task, wait) = crate::awaitdrop();
+        let drain = wait.drain_with_clock(clock);
+        ticks.store(30, Ordering::SeqCst);
+        drop(task);
+
+        let report = block_on(drain);
+        assert_eq!(report.started, base);
+        assert_eq!(report.elapsed, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn fail_fast() {
+        let (task, wait) = crate::awaitdrop();
+        let crashed = task.clone();
+        let id = crashed.id();
+
+        let fut = wait.fail_fast();
+        thread::spawn(move || {
+            let _crashed = crashed;
+            thread::sleep(Duration::from_millis(50));
+            panic!("worker crashed");
+        });
+
+        assert_eq!(block_on(fut), Err(id));
+        assert!(wait.wait().now_or_never().is_none());
+        drop(task);
+        assert_eq!(block_on(wait.fail_fast()), Err(id));
+
+        let (task, wait) = crate::awaitdrop();
+        drop(task);
+        assert_eq!(block_on(wait.fail_fast()), Ok(()));
+    }
+
+    #[test]
+    fn drain_in_drop() {
+        struct Pool {
+            wait: crate::Waiter,
+        }
+
+        impl Drop for Pool {
+            fn drop(&mut self) {
+                self.wait.drain_in_drop(Duration::from_secs(5));
+            }
+        }
+
+        let (task, wait) = crate::awaitdrop();
+        assert!(!wait.drain_in_drop(Duration::from_millis(20)));
+
+        let observer = wait.observer();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(task);
+        });
+        drop(Pool { wait });
+        assert!(observer.is_drained());
+
+        // Far too long to add to the current time.
+        let (task, wait) = crate::awaitdrop();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(task);
+        });
+        assert!(wait.drain_in_drop(Duration::MAX));
+    }
+}
@@ -0,0 +1,47 @@
+use std::future::Future;
+
+use crate::{
+    Barrier,
+    WaitFuture,
+    Waiter,
+};
+
+/// Something whose shutdown can be awaited
+///
+/// Implemented by this crate's waiting handles so that shutdown code can treat
+/// them uniformly. Anything that implements it, and is also `Clone + Send +
+/// Sync + 'static`, can be used as a [AnyWaiter](crate::AnyWaiter).
+pub trait Drainable {
+    /// The future returned from [Drainable::drained]
+    type Future: Future<Output = ()> + Send + 'static;
+
+    /// Wait for everything this handle is tracking to be dropped
+    fn drained(&self) -> Self::Future;
+
+    /// Check whether everything this handle is tracking has been dropped
+    fn is_drained(&self) -> bool;
+}
+
+impl Drainable for Waiter {
+    type Future = WaitFuture;
+
+    fn drained(&self) -> WaitFuture {
+        self.wait()
+    }
+
+    fn is_drained(&self) -> bool {
+        self.is_done()
+    }
+}
+
+impl Drainable for Barrier {
+    type Future = WaitFuture;
+
+    fn drained(&self) -> WaitFuture {
+        self.all_done()
+    }
+
+    fn is_drained(&self) -> bool {
+        self.done.is_drained()
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    any::Any,
+    future::Future,
+    pin::Pin,
+};
+
+use crate::{
+    Drainable,
+    Ref,
+    Waiter,
+};
+
+/// A boxed future that resolves once some group has drained
+///
+/// Returned by [Waiter::boxed] and [AnyWaiter::wait_box].
+pub type BoxWait = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// An object-safe view of a [Ref]-like handle
+///
+/// Lets handles from unrelated subsystems live side by side as
+/// `Box<dyn AnyRef>`, while still being able to get the concrete type back via
+/// `downcast_ref`.
+pub trait AnyRef: Any + Send + Sync {
+    /// Clone this handle into a new box
+    fn clone_box(&self) -> Box<dyn AnyRef>;
+
+    /// Get this handle as [Any] for downcasting
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// An object-safe view of a [Waiter]-like handle
+///
+/// Lets waiters from unrelated subsystems live side by side as
+/// `Box<dyn AnyWaiter>`, while still being able to get the concrete type back
+/// via `downcast_ref`.
+pub trait AnyWaiter: Any + Send + Sync {
+    /// Wait for everything this handle is tracking to be dropped
+    fn wait_box(&self) -> BoxWait;
+
+    /// Check whether everything this handle is tracking has been dropped
+    fn is_drained(&self) -> bool;
+
+    /// Clone this handle into a new box
+    fn clone_box(&self) -> Box<dyn AnyWaiter>;
+
+    /// Get this handle as [Any] for downcasting
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl Waiter {
+    /// Wait for all connected [Ref]s to be dropped, as a [BoxWait]
+    pub fn boxed(&self) -> BoxWait {
+        Box::pin(self.wait())
+    }
+}
+
+impl dyn AnyRef {
+    /// Get the concrete handle, if it's a `T`
+    pub fn downcast_ref<T: AnyRef>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+}
+
+impl dyn AnyWaiter {
+    /// Get the concrete handle, if it's a `T`
+    pub fn downcast_ref<T: AnyWaiter>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+}
+
+impl Clone for Box<dyn AnyRef> {
+    fn clone(&self) -> Self {
+        (**self).clone_box()
+    }
+}
+
+impl Clone for Box<dyn AnyWaiter> {
+    fn clone(&self) -> Self {
+        (**self).clone_box()
+    }
+}
+
+impl AnyRef for Ref {
+    fn clone_box(&self) -> Box<dyn AnyRef> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl<T> AnyWaiter for T
+where
+    T: Drainable + Clone + Send + Sync + 'static,
+{
+    fn wait_box(&self) -> BoxWait {
+        Box::pin(self.drained())
+    }
+
+    fn is_drained(&self) -> bool {
+        Drainable::is_drained(self)
+    }
+
+    fn clone_box(&self) -> Box<dyn AnyWaiter> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::executor::block_on;
+
+    use super::{
+        AnyRef,
+        AnyWaiter,
+    };
+    use crate::{
+        Barrier,
+        Waiter,
+    };
+
+    #[test]
+    fn heterogeneous_waiters() {
+        let (task, wait) = crate::awaitdrop();
+        let (other_task, other_wait) = crate::awaitdrop();
+        let (barrier, workers) = crate::barrier(1);
+
+        let refs: Vec<Box<dyn AnyRef>> = vec![Box::new(task), Box::new(other_task)];
+        let waiters: Vec<Box<dyn AnyWaiter>> =
+            vec![Box::new(wait), other_wait.clone_box(), Box::new(barrier)];
+
+        assert!(waiters[0].downcast_ref::<Waiter>().is_some());
+        assert!(waiters[2].downcast_ref::<Barrier>().is_some());
+        assert!(waiters.iter().all(|w| !w.is_drained()));
+
+        let cloned = refs.clone();
+        drop(refs);
+        assert!(waiters.iter().all(|w| !w.is_drained()));
+        drop(cloned);
+        drop(workers);
+
+        for waiter in &waiters {
+            block_on(waiter.wait_box());
+        }
+        block_on(waiters[0].downcast_ref::<Waiter>().unwrap().boxed());
+    }
+}
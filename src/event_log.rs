@@ -0,0 +1,151 @@
+use std::{
+    collections::VecDeque,
+    mem,
+    time::Instant,
+};
+
+use parking_lot::Mutex;
+
+use crate::RefId;
+
+/// How many events each group keeps before discarding the oldest
+pub const EVENT_LOG_CAPACITY: usize = 256;
+
+/// The kind of a recorded [Event]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EventKind {
+    /// A [Ref](crate::Ref) was created by cloning or upgrading
+    Created,
+    /// A [Ref](crate::Ref) was dropped
+    Dropped,
+    /// A [Waiter](crate::Waiter) started waiting
+    Wait,
+    /// The last [Ref](crate::Ref) was dropped and waiters were woken
+    Wake,
+}
+
+/// A lifecycle event recorded in a group's event log
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    /// What happened
+    pub kind: EventKind,
+    /// When it happened
+    pub at: Instant,
+    /// The [Ref](crate::Ref) involved, for events that concern a single one
+    pub handle: Option<RefId>,
+}
+
+/// A bounded log of the most recent [Event]s in a group.
+#[derive(Default)]
+pub(crate) struct EventLog {
+    events: Mutex<VecDeque<Event>>,
+    // Kept separately, since the change may have scrolled out of the log.
+    last_change: Mutex<Option<Instant>>,
+}
+
+impl EventLog {
+    pub(crate) fn record(&self, kind: EventKind, handle: Option<RefId>) {
+        let event = Event {
+            kind,
+            at: Instant::now(),
+            handle,
+        };
+        if matches!(kind, EventKind::Created | EventKind::Dropped) {
+            *self.last_change.lock() = Some(event.at);
+        }
+        let mut events = self.events.lock();
+        if events.len() == EVENT_LOG_CAPACITY {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.events.lock().capacity() * mem::size_of::<Event>()
+    }
+
+    pub(crate) fn dump(&self) -> Vec<Event> {
+        self.events.lock().iter().copied().collect()
+    }
+
+    pub(crate) fn last_change(&self) -> Option<Instant> {
+        *self.last_change.lock()
+    }
+
+    pub(crate) fn clear(&self) {
+        self.events.lock().clear();
+        self.last_change.lock().take();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        thread,
+        time::Duration,
+    };
+
+    use super::{
+        EventKind,
+        EVENT_LOG_CAPACITY,
+    };
+
+    #[test]
+    fn records_lifecycle() {
+        let (task, wait) = crate::awaitdrop();
+
+        let other = task.clone();
+        let id = other.id();
+        let fut = wait.wait();
+        drop(other);
+        drop(task);
+        futures::executor::block_on(fut);
+
+        let events = wait.events();
+        assert_eq!(events[0].handle, Some(id));
+        assert_eq!(events[2].handle, Some(id));
+        assert_eq!(events[4].handle, None);
+
+        let kinds = events.into_iter().map(|e| e.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                EventKind::Created,
+                EventKind::Wait,
+                EventKind::Dropped,
+                EventKind::Dropped,
+                EventKind::Wake,
+            ]
+        );
+    }
+
+    #[test]
+    fn bounded() {
+        let (task, wait) = crate::awaitdrop();
+
+        for _ in 0..EVENT_LOG_CAPACITY {
+            drop(task.clone());
+        }
+
+        let events = wait.events();
+        assert_eq!(events.len(), EVENT_LOG_CAPACITY);
+        assert_eq!(events[0].kind, EventKind::Created);
+        assert_eq!(events[EVENT_LOG_CAPACITY - 1].kind, EventKind::Dropped);
+    }
+
+    #[test]
+    fn idle_for() {
+        let (task, wait) = crate::awaitdrop();
+        thread::sleep(Duration::from_millis(50));
+        drop(task.clone());
+        let idle = wait.idle_for().unwrap();
+        assert!(idle < Duration::from_millis(50));
+        assert!(wait.age().unwrap() >= Duration::from_millis(50));
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(wait.idle_for().unwrap() >= Duration::from_millis(50));
+        drop(task);
+        assert!(wait.idle_for().is_none());
+    }
+}
@@ -0,0 +1,434 @@
+use std::{
+    future::Future,
+    ops::{
+        Deref,
+        DerefMut,
+    },
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::task::{
+    Spawn,
+    SpawnExt,
+};
+
+use crate::{
+    OverdueRef,
+    Ref,
+    RefId,
+    Shared,
+    Waiter,
+};
+
+/// A value tracked by a [Ref]
+///
+/// The [Ref] is dropped along with the value, so a [Waiter] won't resolve until
+/// every [Guard] in its group has gone away. The value is always dropped first.
+pub struct Guard<T> {
+    // Field order matters: the value has to be dropped before the tracker.
+    value: T,
+    tracker: Ref,
+}
+
+impl<T> Guard<T> {
+    /// Tie `value` to `tracker`
+    pub fn new(value: T, tracker: Ref) -> Self {
+        Guard { value, tracker }
+    }
+
+    /// Get the [Ref] tracking this value
+    pub fn tracker(&self) -> &Ref {
+        &self.tracker
+    }
+
+    /// Split the guard back into its value and [Ref]
+    pub fn into_parts(self) -> (T, Ref) {
+        (self.value, self.tracker)
+    }
+
+    /// Transform the guarded value, keeping it tracked throughout
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Guard<U> {
+        Guard {
+            value: f(self.value),
+            tracker: self.tracker,
+        }
+    }
+
+    /// Attempt to transform the guarded value
+    ///
+    /// The value stays tracked until `f` returns. If it fails, the [Ref] is
+    /// dropped along with the guard.
+    pub fn try_map<U, E>(self, f: impl FnOnce(T) -> Result<U, E>) -> Result<Guard<U>, E> {
+        Ok(Guard {
+            value: f(self.value)?,
+            tracker: self.tracker,
+        })
+    }
+}
+
+/// A shared value whose holders can be awaited, created by [track_arc]
+///
+/// Cloning a [TrackedArc] clones both the [Arc] and its [Ref], so the
+/// [Waiter] resolves once every clone is gone.
+pub struct TrackedArc<T> {
+    // Same as Guard, the value goes first.
+    arc: Arc<T>,
+    tracker: Ref,
+}
+
+/// Wrap `arc` so that every holder of it can be waited on
+///
+/// Only clones of the returned [TrackedArc] are tracked. Clones made from
+/// `arc` itself, or pulled out with [TrackedArc::arc], aren't.
+#[cfg_attr(feature = "debug-tracking", track_caller)]
+pub fn track_arc<T>(arc: Arc<T>) -> (TrackedArc<T>, Waiter) {
+    let (tracker, wait) = crate::awaitdrop();
+    (TrackedArc { arc, tracker }, wait)
+}
+
+impl<T> TrackedArc<T> {
+    /// Get the underlying [Arc]
+    pub fn arc(&self) -> &Arc<T> {
+        &self.arc
+    }
+
+    /// Get the [Ref] tracking this clone
+    pub fn tracker(&self) -> &Ref {
+        &self.tracker
+    }
+
+    /// Split back into the [Arc] and its [Ref]
+    pub fn into_parts(self) -> (Arc<T>, Ref) {
+        (self.arc, self.tracker)
+    }
+}
+
+impl<T> Clone for TrackedArc<T> {
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    fn clone(&self) -> Self {
+        TrackedArc {
+            arc: self.arc.clone(),
+            tracker: self.tracker.clone(),
+        }
+    }
+}
+
+impl<T> Deref for TrackedArc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.arc
+    }
+}
+
+/// A callback that runs when it's dropped, created by [Ref::on_drop]
+pub struct OnDrop<F: FnOnce()>(Option<F>);
+
+impl<F: FnOnce()> OnDrop<F> {
+    /// Forget the callback without running it
+    pub fn cancel(&mut self) {
+        self.0.take();
+    }
+}
+
+impl<F: FnOnce()> Drop for OnDrop<F> {
+    fn drop(&mut self) {
+        if let Some(f) = self.0.take() {
+            f()
+        }
+    }
+}
+
+/// A drain deadline for the [Ref] guarding it, created by
+/// [Ref::with_drain_deadline]
+pub struct Deadline {
+    deadline: Duration,
+    id: RefId,
+    shared: Arc<Shared>,
+}
+
+impl Deadline {
+    /// Get the deadline
+    pub fn get(&self) -> Duration {
+        self.deadline
+    }
+}
+
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        let requested = *self.shared.drain_requested_at.lock();
+        if let Some(elapsed) = requested.map(|at| at.elapsed()) {
+            if elapsed > self.deadline {
+                self.shared.overdue.lock().push(OverdueRef {
+                    id: self.id,
+                    deadline: self.deadline,
+                    elapsed,
+                });
+            }
+        }
+    }
+}
+
+impl Ref {
+    /// Attach a callback to this [Ref] that runs when it's dropped
+    ///
+    /// The callback runs before the [Ref] is released, so it's done by the time
+    /// any [Waiter] could observe the drop.
+    pub fn on_drop<F: FnOnce()>(self, f: F) -> Guard<OnDrop<F>> {
+        self.guard(OnDrop(Some(f)))
+    }
+
+    /// Expect this [Ref] to be dropped within `deadline` of
+    /// [Waiter::request_drain] being called
+    ///
+    /// Missing the deadline doesn't do anything to the [Ref]. It's reported
+    /// through [Waiter::overdue] once the returned [Guard] is finally dropped,
+    /// and not before: a [Ref] that's still hanging on past its deadline
+    /// doesn't show up anywhere. Clones of the [Ref] don't inherit the
+    /// deadline.
+    pub fn with_drain_deadline(self, deadline: Duration) -> Guard<Deadline> {
+        let deadline = Deadline {
+            deadline,
+            id: self.id(),
+            shared: self.inner().shared.clone(),
+        };
+        self.guard(deadline)
+    }
+
+    /// Run `cleanup` on `spawner` when the returned handle is dropped
+    ///
+    /// The [Ref] is only released once the cleanup has finished, so any
+    /// [Waiter] waits for it. This stands in for async drop on tracked
+    /// resources.
+    pub fn defer_async<S, F>(self, spawner: S, cleanup: F) -> DeferAsync<S, F>
+    where
+        S: Spawn,
+        F: Future<Output = ()> + Send + 'static,
+    {
+        DeferAsync {
+            spawner,
+            cleanup: Some((cleanup, self)),
+        }
+    }
+}
+
+/// Asynchronous cleanup that's spawned when it's dropped, created by
+/// [Ref::defer_async]
+pub struct DeferAsync<S: Spawn, F: Future<Output = ()> + Send + 'static> {
+    spawner: S,
+    cleanup: Option<(F, Ref)>,
+}
+
+impl<S: Spawn, F: Future<Output = ()> + Send + 'static> DeferAsync<S, F> {
+    /// Give up on the cleanup, returning the [Ref] without spawning anything
+    pub fn cancel(mut self) -> Ref {
+        let (_, tracker) = self.cleanup.take().unwrap();
+        tracker
+    }
+}
+
+impl<S: Spawn, F: Future<Output = ()> + Send + 'static> Drop for DeferAsync<S, F> {
+    fn drop(&mut self) {
+        if let Some((cleanup, tracker)) = self.cleanup.take() {
+            // If the executor has shut down, the cleanup is dropped unfinished
+            // and the Ref is released right away, since there's nothing left
+            // to run it on.
+            let _ = self.spawner.spawn(async move {
+                cleanup.await;
+                drop(tracker);
+            });
+        }
+    }
+}
+
+/// Hold on to a [Ref] until the end of the current scope
+///
+/// The [Ref] is released however the scope is left, including early returns
+/// and panics. With a callback, it's run first, as with [Ref::on_drop].
+///
+/// ```
+/// # let (task, wait) = awaitdrop::awaitdrop();
+/// {
+///     awaitdrop::defer!(task, || println!("done"));
+///     // ...
+/// }
+/// wait.wait_blocking();
+/// ```
+#[macro_export]
+macro_rules! defer {
+    ($tracker:expr $(,)?) => {
+        let _guard: $crate::Ref = $tracker;
+    };
+    ($tracker:expr, $callback:expr $(,)?) => {
+        let _guard = $crate::Ref::on_drop($tracker, $callback);
+    };
+}
+
+impl<T> From<(T, Ref)> for Guard<T> {
+    fn from((value, tracker): (T, Ref)) -> Self {
+        Guard::new(value, tracker)
+    }
+}
+
+impl<T> From<Guard<T>> for (T, Ref) {
+    fn from(guard: Guard<T>) -> Self {
+        guard.into_parts()
+    }
+}
+
+impl<T> AsRef<Ref> for Guard<T> {
+    fn as_ref(&self) -> &Ref {
+        &self.tracker
+    }
+}
+
+impl<T> Deref for Guard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Guard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        panic::{
+            self,
+            AssertUnwindSafe,
+        },
+        sync::{
+            atomic::{
+                AtomicBool,
+                Ordering,
+            },
+            Arc,
+        },
+    };
+
+    use futures::{
+        executor::LocalPool,
+        FutureExt,
+    };
+
+    use super::Guard;
+
+    #[test]
+    fn map_keeps_tracking() {
+        let (task, wait) = crate::awaitdrop();
+
+        let guard = task.guard(21);
+        let guard = guard.map(|v| v * 2);
+        assert_eq!(*guard, 42);
+        assert!(wait.wait().now_or_never().is_none());
+
+        let guard = guard.try_map(u8::try_from).unwrap();
+        let (value, tracker): (u8, _) = guard.into();
+        let guard = Guard::from((value, tracker));
+        assert!(wait.wait().now_or_never().is_none());
+        assert!(wait.wait().now_or_never().is_none());
+
+        let failed = guard.try_map(|_| Err::<(), _>("nope"));
+        assert!(failed.is_err());
+        wait.wait().now_or_never().unwrap();
+    }
+
+    #[test]
+    fn on_drop_before_release() {
+        let (task, wait) = crate::awaitdrop();
+        let observer = wait.observer();
+
+        let guard = task.on_drop(move || assert_eq!(observer.count(), 1));
+        drop(guard);
+        wait.wait().now_or_never().unwrap();
+
+        let (task, wait) = crate::awaitdrop();
+        let mut guard = task.on_drop(|| panic!("cancelled"));
+        guard.cancel();
+        drop(guard);
+        wait.wait().now_or_never().unwrap();
+    }
+
+    #[test]
+    fn track_arc() {
+        let (config, wait) = super::track_arc(Arc::new(42));
+        let other = config.clone();
+        assert_eq!(*other, 42);
+        assert!(Arc::ptr_eq(config.arc(), other.arc()));
+
+        drop(config);
+        assert!(wait.wait().now_or_never().is_none());
+        let (arc, tracker) = other.into_parts();
+        drop(tracker);
+        wait.wait().now_or_never().unwrap();
+        assert_eq!(*arc, 42);
+    }
+
+    #[test]
+    fn defer_async() {
+        let (task, wait) = crate::awaitdrop();
+        let mut pool = LocalPool::new();
+        let cleaned = Arc::new(AtomicBool::new(false));
+
+        let deferred = task.clone().defer_async(pool.spawner(), {
+            let cleaned = cleaned.clone();
+            async move { cleaned.store(true, Ordering::SeqCst) }
+        });
+        drop(task);
+        drop(deferred);
+        assert!(wait.wait().now_or_never().is_none());
+
+        pool.run();
+        assert!(cleaned.load(Ordering::SeqCst));
+        wait.wait().now_or_never().unwrap();
+
+        let (task, wait) = crate::awaitdrop();
+        let deferred = task.defer_async(pool.spawner(), async { panic!("cancelled") });
+        drop(deferred.cancel());
+        pool.run();
+        wait.wait().now_or_never().unwrap();
+    }
+
+    #[test]
+    fn defer() {
+        fn work(task: crate::Ref, ran: &AtomicBool, fail: bool) -> Result<(), ()> {
+            crate::defer!(task, || ran.store(true, Ordering::SeqCst));
+            if fail {
+                return Err(());
+            }
+            Ok(())
+        }
+
+        let (task, wait) = crate::awaitdrop();
+        let ran = AtomicBool::new(false);
+        assert!(work(task, &ran, true).is_err());
+        assert!(ran.load(Ordering::SeqCst));
+        wait.wait().now_or_never().unwrap();
+
+        let (task, wait) = crate::awaitdrop();
+        let result = panic::catch_unwind(AssertUnwindSafe(move || {
+            crate::defer!(task);
+            panic!("boom");
+        }));
+        assert!(result.is_err());
+        wait.wait().now_or_never().unwrap();
+    }
+
+    #[cfg(feature = "debug-tracking")]
+    #[test]
+    fn tracked_arc_locations() {
+        let (tracked, _wait) = super::track_arc(Arc::new(()));
+        assert_eq!(tracked.tracker().location().line(), line!() - 1);
+        assert_eq!(tracked.tracker().location().file(), file!());
+
+        let other = tracked.clone();
+        assert_eq!(other.tracker().location().line(), line!() - 1);
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    any::Any,
+    sync::Arc,
+};
+
+use parking_lot::Mutex;
+
+use crate::RefId;
+
+/// A lifecycle event for a [Ref](crate::Ref), passed to hooks registered via
+/// [Waiter::set_hook](crate::Waiter::set_hook) along with the
+/// [Ref](crate::Ref)'s id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RefEvent {
+    /// A new [Ref](crate::Ref) was created, either by cloning or by upgrading
+    /// a [Weak](crate::Weak).
+    Created,
+    /// A [Ref](crate::Ref) was dropped.
+    Dropped,
+}
+
+/// Arbitrary metadata attached to a [Ref](crate::Ref) by an interceptor
+/// registered via [Waiter::set_interceptor](crate::Waiter::set_interceptor).
+pub type RefContext = Arc<dyn Any + Send + Sync>;
+
+type Hook = Arc<dyn Fn(RefEvent, RefId) + Send + Sync>;
+type Interceptor = Arc<dyn Fn(RefId) -> Option<RefContext> + Send + Sync>;
+
+#[derive(Default)]
+pub(crate) struct HookSlot {
+    hook: Mutex<Option<Hook>>,
+    interceptor: Mutex<Option<Interceptor>>,
+}
+
+impl HookSlot {
+    pub(crate) fn set(&self, hook: impl Fn(RefEvent, RefId) + Send + Sync + 'static) {
+        *self.hook.lock() = Some(Arc::new(hook));
+    }
+
+    pub(crate) fn set_interceptor(
+        &self,
+        interceptor: impl Fn(RefId) -> Option<RefContext> + Send + Sync + 'static,
+    ) {
+        *self.interceptor.lock() = Some(Arc::new(interceptor));
+    }
+
+    pub(crate) fn clear(&self) {
+        self.hook.lock().take();
+        self.interceptor.lock().take();
+    }
+
+    pub(crate) fn intercept(&self, id: RefId) -> Option<RefContext> {
+        // Same as for hooks, the interceptor may want to touch other refs.
+        let interceptor = self.interceptor.lock().clone();
+        interceptor.and_then(|interceptor| interceptor(id))
+    }
+
+    pub(crate) fn emit(&self, event: RefEvent, id: RefId) {
+        // Call outside of the lock so that hooks are free to clone or drop refs
+        // themselves.
+        let hook = self.hook.lock().clone();
+        if let Some(hook) = hook {
+            hook(event, id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+    };
+
+    use parking_lot::Mutex;
+
+    use super::{
+        RefContext,
+        RefEvent,
+    };
+
+    #[test]
+    fn hook_sees_clone_and_drop() {
+        let (task, wait) = crate::awaitdrop();
+        let created = Arc::new(AtomicUsize::new(0));
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+
+        wait.set_hook({
+            let created = created.clone();
+            let dropped = dropped.clone();
+            let seen = seen.clone();
+            move |ev, id| {
+                let counter = match ev {
+                    RefEvent::Created => &created,
+                    RefEvent::Dropped => &dropped,
+                };
+                counter.fetch_add(1, Ordering::SeqCst);
+                seen.lock().push((ev, id));
+            }
+        });
+
+        let other = task.clone();
+        let other_id = other.id();
+        let weak = other.downgrade();
+        drop(weak.upgrade());
+        drop(other);
+        drop(task);
+
+        assert_eq!(created.load(Ordering::SeqCst), 2);
+        assert_eq!(dropped.load(Ordering::SeqCst), 3);
+        let seen = seen.lock();
+        assert_eq!(seen[0], (RefEvent::Created, other_id));
+        assert!(seen.contains(&(RefEvent::Dropped, other_id)));
+        wait.wait_blocking();
+    }
+
+    #[test]
+    fn interceptor_attaches_context() {
+        let (task, wait) = crate::awaitdrop();
+        wait.set_interceptor(|id| Some(Arc::new(format!("request {}", id)) as RefContext));
+
+        assert!(task.context().is_none());
+        let other = task.clone();
+        let context = other.context().unwrap().downcast_ref::<String>().unwrap();
+        assert_eq!(*context, format!("request {}", other.id()));
+
+        let upgraded = other.downgrade().upgrade().unwrap();
+        assert!(upgraded.context().is_some());
+
+        drop((task, other, upgraded));
+        wait.wait_blocking();
+    }
+}
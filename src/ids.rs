@@ -0,0 +1,128 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+};
+
+use parking_lot::Mutex;
+
+use crate::{
+    Ref,
+    Waiter,
+};
+
+/// A group whose members are explicit ids rather than anonymous [Ref]s
+///
+/// Each id holds a [Ref] in the group while it's in the set, so the
+/// [Waiter] resolves once the set is [closed](IdGroup::close) and every id has
+/// been removed. Until then, [IdGroup::outstanding] lists who's left.
+pub struct IdGroup<K> {
+    // Stands in for the creator's own Ref, so that the group doesn't drain
+    // before the first id is added.
+    anchor: Mutex<Option<Ref>>,
+    members: Mutex<HashMap<K, Ref>>,
+    waiter: Waiter,
+}
+
+impl<K: Eq + Hash> IdGroup<K> {
+    /// Create a new, empty set
+    pub fn new() -> Self {
+        let (anchor, waiter) = crate::awaitdrop();
+        IdGroup {
+            anchor: Mutex::new(Some(anchor)),
+            members: Default::default(),
+            waiter,
+        }
+    }
+
+    /// Get a [Waiter] for the set
+    pub fn waiter(&self) -> &Waiter {
+        &self.waiter
+    }
+
+    /// Add `id` to the set
+    ///
+    /// Returns `false` if it was already there, or if the set has already
+    /// drained and can't take new members.
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn insert(&self, id: K) -> bool {
+        let mut members = self.members.lock();
+        if members.contains_key(&id) {
+            return false;
+        }
+        // Any member's Ref will do to make a new one, falling back on the
+        // anchor if the set is empty.
+        let r = match members.values().next() {
+            Some(r) => r.clone(),
+            None => match &*self.anchor.lock() {
+                Some(anchor) => anchor.clone(),
+                None => return false,
+            },
+        };
+        members.insert(id, r);
+        true
+    }
+
+    /// Remove `id` from the set, returning whether it was there
+    pub fn remove(&self, id: &K) -> bool {
+        let removed = self.members.lock().remove(id);
+        removed.is_some()
+    }
+
+    /// Stop waiting on anything but the ids already in the set
+    ///
+    /// Once the set is closed, the [Waiter] resolves as soon as it's empty.
+    /// New ids can still be added as long as some are left.
+    pub fn close(&self) {
+        let anchor = self.anchor.lock().take();
+        drop(anchor);
+    }
+
+    /// Get the ids still in the set
+    pub fn outstanding(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        self.members.lock().keys().cloned().collect()
+    }
+}
+
+impl<K: Eq + Hash> Default for IdGroup<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::FutureExt;
+
+    use super::IdGroup;
+
+    #[test]
+    fn waits_for_ids() {
+        let group = IdGroup::new();
+        assert!(group.insert("db"));
+        assert!(group.insert("cache"));
+        assert!(!group.insert("db"));
+
+        group.close();
+        assert!(group.waiter().wait().now_or_never().is_none());
+        assert!(group.remove(&"db"));
+        assert_eq!(group.outstanding(), ["cache"]);
+        assert!(group.waiter().wait().now_or_never().is_none());
+
+        assert!(group.remove(&"cache"));
+        group.waiter().wait().now_or_never().unwrap();
+        assert!(!group.insert("late"));
+    }
+
+    #[cfg(feature = "debug-tracking")]
+    #[test]
+    fn insert_location() {
+        let group = IdGroup::new();
+        group.insert("db");
+        let location = group.members.lock()["db"].location();
+        assert_eq!(location.line(), line!() - 2);
+        assert_eq!(location.file(), file!());
+    }
+}
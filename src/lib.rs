@@ -11,17 +11,38 @@
 //!   create a new [Ref].
 //! * Everything is cloneable and behaves as one would expect - cloned [Ref]s
 //!   will all block every cloned [Waiter], which can be awaited concurrently.
+//! * Every handle and future is [Unpin], so they can be embedded in
+//!   hand-written futures and polled through [Pin::new] without any pin
+//!   projection.
 
 #![warn(missing_docs)]
 
+#[cfg(feature = "debug-tracking")]
+use std::panic::Location;
 use std::{
+    any::Any,
+    cell::Cell,
+    fmt,
     future::{
         Future,
         IntoFuture,
     },
+    mem,
+    num::NonZeroU64,
+    panic::{
+        self,
+        AssertUnwindSafe,
+    },
     pin::Pin,
     sync::{
         self,
+        atomic::{
+            self,
+            AtomicBool,
+            AtomicU64,
+            AtomicUsize,
+            Ordering,
+        },
         Arc,
     },
     task::{
@@ -29,244 +50,2265 @@ use std::{
         Poll,
         Waker,
     },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
-use futures::executor::block_on;
+use futures::{
+    executor::block_on,
+    task::{
+        Spawn,
+        SpawnExt,
+    },
+};
 use parking_lot::Mutex;
 use slotmap::{
     DefaultKey,
+    Key,
     SlotMap,
 };
 
+mod admit;
+pub use admit::{
+    Admit,
+    AdmitError,
+};
+
+mod ambient;
+pub use ambient::{
+    Entered,
+    Instrumented,
+};
+
+mod barrier;
+pub use barrier::{
+    barrier,
+    Barrier,
+    BarrierWorker,
+};
+
+mod drain;
+pub use drain::{
+    DrainFuture,
+    DrainReport,
+    FailFast,
+    OverdueRef,
+};
+
+mod drainable;
+pub use drainable::Drainable;
+
+mod dynamic;
+pub use dynamic::{
+    AnyRef,
+    AnyWaiter,
+    BoxWait,
+};
+
+mod guard;
+pub use guard::{
+    track_arc,
+    Deadline,
+    DeferAsync,
+    Guard,
+    OnDrop,
+    TrackedArc,
+};
+
+mod ids;
+pub use ids::IdGroup;
+
+mod notify;
+pub use notify::{
+    Notified,
+    Notify,
+};
+
+mod pool;
+pub use pool::GroupPool;
+
+mod raw;
+
+mod scoped;
+pub use scoped::{
+    ScopedRef,
+    ThreadScope,
+};
+
+mod signal;
+pub use signal::{
+    AllOf,
+    DoneSignal,
+    Escalation,
+    FirstOf,
+    MapDone,
+    OrElse,
+};
+
+mod stream;
+pub use stream::{
+    DrainStream,
+    WhileAlive,
+};
+
+mod unique;
+pub use unique::UniqueRef;
+
+mod watch;
+pub use watch::{
+    AboveFuture,
+    AcquireFuture,
+    AnyDropFuture,
+    BelowFuture,
+};
+
+#[cfg(feature = "event-log")]
+mod event_log;
+#[cfg(feature = "event-log")]
+pub use event_log::{
+    Event,
+    EventKind,
+    EVENT_LOG_CAPACITY,
+};
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(feature = "hooks")]
+mod hooks;
+#[cfg(feature = "hooks")]
+pub use hooks::{
+    RefContext,
+    RefEvent,
+};
+
 #[derive(Default)]
 struct Wakers {
-    wakers: SlotMap<DefaultKey, Option<Waker>>,
+    // Most groups only ever have one waiter, so the first slot lives here,
+    // under the null key, and the slotmap isn't allocated until a second one
+    // is needed.
+    inline: Option<Option<Waker>>,
+    wakers: Option<SlotMap<DefaultKey, Option<Waker>>>,
 }
 
 impl Wakers {
     fn allocate(&mut self) -> DefaultKey {
-        self.wakers.insert(None)
+        self.allocate_with(None)
+    }
+
+    fn allocate_with(&mut self, waker: Option<Waker>) -> DefaultKey {
+        if self.inline.is_none() {
+            self.inline = Some(waker);
+            return DefaultKey::null();
+        }
+        self.wakers.get_or_insert_with(SlotMap::new).insert(waker)
+    }
+
+    /// Store `waker` in the slot at `idx`, allocating one first if needed.
+    fn register(&mut self, idx: &mut Option<DefaultKey>, waker: &Waker) {
+        match idx {
+            Some(idx) => self.insert(*idx, waker.clone()),
+            None => *idx = Some(self.allocate_with(Some(waker.clone()))),
+        }
     }
 
     fn insert(&mut self, idx: DefaultKey, waker: Waker) {
-        if let Some(w) = self.wakers.get_mut(idx) {
+        let slot = if idx.is_null() {
+            self.inline.as_mut()
+        } else {
+            self.wakers.as_mut().and_then(|w| w.get_mut(idx))
+        };
+        if let Some(w) = slot {
             *w = Some(waker)
         }
     }
 
     fn remove(&mut self, idx: DefaultKey) -> Option<Waker> {
-        self.wakers.remove(idx).and_then(|w| w)
+        if idx.is_null() {
+            self.inline.take().flatten()
+        } else {
+            self.wakers.as_mut()?.remove(idx).flatten()
+        }
+    }
+
+    fn memory_usage(&self) -> usize {
+        // A slotmap slot also carries its version, hence the extra u32.
+        self.wakers.as_ref().map_or(0, |w| w.capacity()) * mem::size_of::<(Option<Waker>, u32)>()
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
+        Wakers {
+            inline: None,
+            wakers: Some(SlotMap::with_capacity(capacity)),
+        }
     }
 
-    fn wake_all(&mut self) {
+    /// Take the waker out of some slot that has one, leaving the slot itself.
+    fn take_one(&mut self) -> Option<(DefaultKey, Waker)> {
+        if let Some(waker) = self.inline.as_mut().and_then(Option::take) {
+            return Some((DefaultKey::null(), waker));
+        }
         self.wakers
-            .drain()
-            .filter_map(|(_, w)| w)
-            .for_each(|w| w.wake());
+            .as_mut()?
+            .iter_mut()
+            .find_map(|(idx, waker)| Some((idx, waker.take()?)))
+    }
+
+    fn take_all(&mut self) -> impl Iterator<Item = Waker> {
+        // Leave the slots in place: they belong to their futures, which may
+        // need to register again if they get woken more than once.
+        let inline = self.inline.as_mut().and_then(Option::take);
+        let rest = match &mut self.wakers {
+            Some(wakers) => wakers.values_mut().filter_map(Option::take).collect(),
+            None => Vec::new(),
+        };
+        inline.into_iter().chain(rest)
     }
 }
 
-/// A reference whose drop can be awaited
+/// Wake everything registered in `wakers`.
 ///
-/// When cloned, creates a new reference attached to the same [Waiter].
-#[derive(Clone)]
-pub struct Weak {
-    count: Option<sync::Weak<()>>,
-    wakers: Arc<Mutex<Wakers>>,
+/// The wakers are called outside of the lock. See [wake] for how panics are
+/// handled.
+fn wake_all(wakers: &Mutex<Wakers>) {
+    let wakers = wakers.lock().take_all();
+    wake(wakers);
 }
 
-impl Weak {
-    /// Attempt to upgrade to a strong [Ref]
-    pub fn upgrade(&self) -> Option<Ref> {
-        let weak = self.count.as_ref()?;
-        let strong = sync::Weak::upgrade(weak)?;
+/// Call every one of `wakers`.
+///
+/// A panicking waker doesn't stop the rest from being woken. The first panic
+/// is resumed once they've all been called, unless the thread is already
+/// panicking.
+fn wake(wakers: impl IntoIterator<Item = Waker>) {
+    resume(wake_caught(wakers));
+}
 
-        Some(Ref {
-            count: Some(strong),
-            wakers: self.wakers.clone(),
-        })
+/// Call every one of `wakers`, handing back the first panic instead of
+/// resuming it, so that the caller can finish its own notifications first.
+fn wake_caught(wakers: impl IntoIterator<Item = Waker>) -> Option<Box<dyn Any + Send>> {
+    let mut panic = None;
+    for waker in wakers {
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| waker.wake())) {
+            panic.get_or_insert(payload);
+        }
     }
+    panic
 }
 
-/// A reference whose drop can be awaited
-///
-/// When cloned, creates a new reference attached to the same [Waiter].
-#[derive(Clone)]
-pub struct Ref {
-    count: Option<Arc<()>>,
-    wakers: Arc<Mutex<Wakers>>,
+/// Resume a panic caught by [wake_caught], unless the thread is already
+/// panicking.
+fn resume(panic: Option<Box<dyn Any + Send>>) {
+    if let Some(payload) = panic {
+        if !thread::panicking() {
+            panic::resume_unwind(payload);
+        }
+    }
 }
 
-impl Ref {
-    /// Get a new [Weak] that doesn't contribute to the ref count.
-    pub fn downgrade(&self) -> Weak {
-        let strong = self.count.as_ref().unwrap();
-        let weak = Arc::downgrade(strong);
-        Weak {
-            count: Some(weak),
-            wakers: self.wakers.clone(),
+/// State shared between every handle to a group.
+#[derive(Default)]
+struct Shared {
+    // The current group's GroupId. Set anew each time the allocation is used
+    // for a group.
+    id: AtomicU64,
+    wakers: Mutex<Wakers>,
+    // The current group's count, for Refs reporting a drop to the watch once
+    // they've let go of their own.
+    count: Mutex<sync::Weak<Inner>>,
+    // Wakers from `Waiter::register_waker`. There's no future behind them to
+    // poll again, so unlike `wakers` they're only woken once the group is done.
+    // They never use an inline slot: their tokens can be copied and outlive
+    // the slot, so they need the slotmap's versioning to go stale.
+    raw_wakers: Mutex<SlotMap<DefaultKey, Option<Waker>>>,
+    // Wakers from `Waiter::fail_fast` futures, which are also woken when a Ref
+    // is dropped during a panic. Their final wake goes through `wakers`.
+    fail_fast_wakers: Mutex<Wakers>,
+    watch: watch::Watch,
+    // The most live Refs that `Weak::upgrade` and `Ref::try_ref` will allow.
+    // Zero means unlimited.
+    quota: AtomicUsize,
+    // Non-zero while some Ref is checking whether it's the last one, and for
+    // good once one has found out that it is. Upgrades fail while it's set.
+    sealing: AtomicUsize,
+    drain_requested: AtomicBool,
+    // Set by `Waiter::force_release`. Waiters treat the group as drained from
+    // then on, whatever the count says.
+    released: AtomicBool,
+    // The number of live Waiters, including those inside wait futures.
+    waiters: AtomicUsize,
+    abandoned: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+    // Where to run the final wake, if not inline in the last drop.
+    wake_spawner: Mutex<Option<Arc<dyn Spawn + Send + Sync>>>,
+    drain_requested_at: Mutex<Option<Instant>>,
+    // When the current group was created. Kept here rather than in `Inner`, so
+    // that reading it doesn't take a count on the group.
+    started: Mutex<Option<Instant>>,
+    overdue: Mutex<Vec<OverdueRef>>,
+    // Pending polls of wait futures that had already registered.
+    #[cfg(feature = "debug-tracking")]
+    spurious_wakes: AtomicU64,
+    // The first Ref to be dropped during a panic, for `Waiter::fail_fast`.
+    panicked: Mutex<Option<RefId>>,
+    // Refs kept alive on purpose by `Ref::leak`. Since they point back here,
+    // neither they nor the group are ever freed.
+    leaked: Mutex<Vec<(&'static str, Ref)>>,
+    #[cfg(feature = "hooks")]
+    hook: hooks::HookSlot,
+    #[cfg(feature = "event-log")]
+    events: event_log::EventLog,
+}
+
+impl Shared {
+    /// Take every waker that's waiting for the group to be done, whether from
+    /// a future or [Waiter::register_waker].
+    fn take_final_wakers(&self) -> Vec<Waker> {
+        let mut wakers = self.wakers.lock().take_all().collect::<Vec<_>>();
+        wakers.extend(self.raw_wakers.lock().values_mut().filter_map(Option::take));
+        wakers
+    }
+
+    /// Check whether a new [Ref] would be admitted with `count` already live,
+    /// without taking a count to find out.
+    ///
+    /// [Ref::admitted] still has the final say, but checking first keeps
+    /// hopeless attempts from taking and giving back counts, which wakes
+    /// everything watching the group.
+    fn admits(&self, count: usize) -> bool {
+        let quota = self.quota.load(Ordering::Relaxed);
+        (quota == 0 || count < quota)
+            && self.sealing.load(Ordering::Relaxed) == 0
+            && !self.released.load(Ordering::Acquire)
+    }
+
+    /// Approximate the heap memory used by a group, including the shared
+    /// allocations themselves.
+    fn memory_usage(&self) -> usize {
+        // Each Arc allocation carries two reference counts.
+        let arc = 2 * mem::size_of::<usize>();
+        let mut total = arc + mem::size_of::<Shared>() + arc + mem::size_of::<Inner>();
+        total += self.wakers.lock().memory_usage();
+        total += self.raw_wakers.lock().capacity() * mem::size_of::<(Option<Waker>, u32)>();
+        total += self.fail_fast_wakers.lock().memory_usage();
+        total += self.watch.memory_usage();
+        total += self.overdue.lock().capacity() * mem::size_of::<OverdueRef>();
+        total += self.leaked.lock().capacity() * mem::size_of::<(&'static str, Ref)>();
+        #[cfg(feature = "event-log")]
+        {
+            total += self.events.memory_usage();
         }
+        total
+    }
+
+    /// Prepare the state of a finished group to be used for a new one.
+    fn reset(&self) {
+        self.quota.store(0, Ordering::Relaxed);
+        self.sealing.store(0, Ordering::Relaxed);
+        self.drain_requested.store(false, Ordering::Relaxed);
+        self.released.store(false, Ordering::Relaxed);
+        self.abandoned.lock().take();
+        self.wake_spawner.lock().take();
+        self.raw_wakers.lock().clear();
+        self.watch.reset();
+        self.drain_requested_at.lock().take();
+        self.overdue.lock().clear();
+        self.panicked.lock().take();
+        #[cfg(feature = "debug-tracking")]
+        self.spurious_wakes.store(0, Ordering::Relaxed);
+        #[cfg(feature = "hooks")]
+        self.hook.clear();
+        #[cfg(feature = "event-log")]
+        self.events.clear();
     }
 }
 
-impl Drop for Ref {
-    fn drop(&mut self) {
-        if Arc::try_unwrap(self.count.take().unwrap()).is_ok() {
-            self.wakers.lock().wake_all();
+/// The allocation shared by every [Ref] in a group.
+///
+/// Its strong count is the number of live [Ref]s, so cloning or dropping one
+/// costs the same as cloning or dropping an [Arc]. Waking the [Waiter]s is
+/// left to its destructor, which runs exactly once, when the last [Ref] goes
+/// away.
+struct Inner {
+    shared: Arc<Shared>,
+    id: GroupId,
+}
+
+impl Inner {
+    /// Give up a strong count on the group, letting anything watching it know.
+    ///
+    /// `dropped` is whether the count belonged to a [Ref]. Counts the crate
+    /// only takes briefly, like a failed upgrade, aren't drops, but they still
+    /// have to be reported: a [Ref] dropped while one was held would have
+    /// reported a count that was one too high.
+    fn release(self: Arc<Self>, dropped: bool) {
+        // Individual drops only need to be reported while the group is being
+        // watched. Hold on to the shared state in that case, since `self` may
+        // well be the last thing keeping it alive.
+        let epoch = watch::epoch();
+        if self.shared.watch.is_watched() {
+            let shared = self.shared.clone();
+            drop(self);
+            shared.watch.report(&shared, dropped);
+            return;
+        }
+
+        // A watcher may have registered between the check and the decrement,
+        // in which case the group has to be looked up again to tell it.
+        let id = self.id;
+        drop(self);
+        if let Some(shared) = watch::watched_since(epoch, id) {
+            shared.watch.report(&shared, dropped);
         }
     }
 }
 
-/// An awaitable handle to some number of references that will eventually be
-/// dropped
-#[derive(Clone)]
-pub struct Waiter {
-    wakers: Arc<Mutex<Wakers>>,
-    count: sync::Weak<()>,
+/// Get a handle id that's unique across the whole process.
+///
+/// Cloning has to stay as cheap as cloning an [Arc], so rather than having
+/// every clone bump a counter next to the group's ref count, each thread
+/// reserves ids from a global counter a block at a time.
+fn next_handle() -> u64 {
+    const BLOCK: u64 = 1024;
+    static NEXT_BLOCK: AtomicU64 = AtomicU64::new(0);
+    thread_local! {
+        static RESERVED: Cell<(u64, u64)> = const { Cell::new((0, 0)) };
+    }
+
+    let next = RESERVED.try_with(|reserved| {
+        let (mut next, mut end) = reserved.get();
+        if next == end {
+            next = NEXT_BLOCK.fetch_add(BLOCK, Ordering::Relaxed);
+            end = next + BLOCK;
+        }
+        reserved.set((next + 1, end));
+        next
+    });
+    // Thread-locals are gone while the thread is shutting down, so take a
+    // block of one in that case.
+    next.unwrap_or_else(|_| NEXT_BLOCK.fetch_add(1, Ordering::Relaxed))
 }
 
-impl Waiter {
-    /// Wait for all connected [Ref]s to be dropped in a blocking manner
-    pub fn wait_blocking(&self) {
-        block_on(self.wait())
+/// Unique identifier for a group, as returned by [Waiter::group_id]
+///
+/// Ids come from a process-wide counter and are never reused, including when
+/// a group's allocations are recycled through a [GroupPool] or
+/// [Waiter::try_reuse].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GroupId(NonZeroU64);
+
+impl GroupId {
+    fn next() -> GroupId {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+        GroupId(NonZeroU64::new(id).expect("group ids exhausted"))
     }
 
-    /// Wait for all connected [Ref]s to be dropped
-    pub fn wait(&self) -> WaitFuture {
-        let idx = self.wakers.lock().allocate();
-        let count = self.count.clone();
-        let wakers = self.wakers.clone();
-        WaitFuture { idx, wakers, count }
+    /// Get the id as a plain integer
+    pub fn get(self) -> u64 {
+        self.0.get()
     }
 }
 
-/// The future returned from [Waiter::wait]
+impl fmt::Display for GroupId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Unique identifier for a [Ref], as returned by [Ref::id]
 ///
-/// Resolves when all connected [Ref]s have been dropped.
-pub struct WaitFuture {
-    idx: DefaultKey,
-    count: sync::Weak<()>,
-    wakers: Arc<Mutex<Wakers>>,
+/// Handle ids are unique within their group, but they aren't handed out in
+/// order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RefId {
+    /// The group the [Ref] belongs to
+    pub group: GroupId,
+    /// The [Ref] itself
+    pub handle: u64,
 }
 
-impl Drop for WaitFuture {
-    fn drop(&mut self) {
-        self.wakers.lock().remove(self.idx);
+impl fmt::Display for RefId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.group, self.handle)
     }
 }
 
-impl Future for WaitFuture {
-    type Output = ();
+impl Drop for Inner {
+    fn drop(&mut self) {
+        #[cfg(feature = "event-log")]
+        self.shared.events.record(EventKind::Wake, None);
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.wakers.lock().insert(self.idx, cx.waker().clone());
-        if sync::Weak::strong_count(&self.count) == 0 {
-            Poll::Ready(())
+        let spawner = self.shared.wake_spawner.lock().clone();
+        let deferred = spawner.and_then(|spawner| {
+            let shared = self.shared.clone();
+            spawner
+                .spawn(async move { wake(shared.take_final_wakers()) })
+                .ok()
+        });
+        // Wake inline if that's the strategy, or if the executor has gone away.
+        // A panicking waker is only resumed once the watch has heard about the
+        // drain too, so that it can't strand anyone waiting there.
+        let panic = if deferred.is_none() {
+            wake_caught(self.shared.take_final_wakers())
         } else {
-            Poll::Pending
-        }
+            None
+        };
+        self.shared.watch.dropped(0);
+        resume(panic);
     }
 }
 
-impl IntoFuture for Waiter {
-    type IntoFuture = WaitFuture;
-    type Output = ();
-    fn into_future(self) -> Self::IntoFuture {
-        self.wait()
+/// A reference whose drop can be awaited
+///
+/// When cloned, creates a new reference attached to the same [Waiter].
+#[derive(Clone)]
+pub struct Weak {
+    inner: sync::Weak<Inner>,
+}
+
+impl Weak {
+    /// Attempt to upgrade to a strong [Ref]
+    ///
+    /// Fails if every [Ref] has been dropped, or if the group is already at its
+    /// [quota](Waiter::set_quota).
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn upgrade(&self) -> Option<Ref> {
+        let inner = self.inner.upgrade()?;
+        Ref::admitted(inner)
+    }
+
+    /// Attempt to upgrade each of `weaks`, keeping their positions
+    ///
+    /// Each upgrade can fail on its own, as with [Weak::upgrade].
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn upgrade_all(weaks: &[Weak]) -> Vec<Option<Ref>> {
+        // A plain loop rather than an iterator, so that the caller's location
+        // makes it through to each upgrade.
+        let mut refs = Vec::with_capacity(weaks.len());
+        for weak in weaks {
+            refs.push(weak.upgrade());
+        }
+        refs
+    }
+
+    /// Get the number of live [Ref]s in the group
+    pub fn strong_count(&self) -> usize {
+        self.inner.strong_count()
+    }
+
+    /// Check whether every [Ref] in the group has been dropped
+    ///
+    /// Once this returns `true`, [Weak::upgrade] will never succeed again.
+    pub fn is_dangling(&self) -> bool {
+        self.strong_count() == 0
     }
 }
 
-/// Create a new [Ref] and [Waiter]
+/// A reference whose drop can be awaited
 ///
-/// The [Waiter] will resolve when the [Ref] and all clones of it have been
-/// dropped.
-pub fn awaitdrop() -> (Ref, Waiter) {
-    let task = Ref {
-        wakers: Default::default(),
-        count: Some(Default::default()),
-    };
-    let wait = Waiter {
-        count: Arc::downgrade(task.count.as_ref().unwrap()),
-        wakers: task.wakers.clone(),
-    };
-
-    (task, wait)
+/// When cloned, creates a new reference attached to the same [Waiter].
+pub struct Ref {
+    inner: Option<Arc<Inner>>,
+    // The group half of the id comes from `inner`.
+    handle: u64,
+    #[cfg(feature = "debug-tracking")]
+    location: &'static Location<'static>,
+    #[cfg(feature = "hooks")]
+    context: Option<RefContext>,
 }
 
-#[cfg(test)]
-mod test {
-    use std::{
-        thread,
-        time::{
-            self,
-            Duration,
-        },
-    };
+impl Ref {
+    /// Wrap a freshly incremented `inner`, letting anything watching the
+    /// group know about it.
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    fn created(inner: Arc<Inner>) -> Ref {
+        let handle = next_handle();
+        #[cfg(any(feature = "hooks", feature = "event-log"))]
+        let id = RefId {
+            group: inner.id,
+            handle,
+        };
 
-    use futures::executor::block_on;
+        #[cfg(feature = "hooks")]
+        let context = inner.shared.hook.intercept(id);
+        #[cfg(feature = "hooks")]
+        inner.shared.hook.emit(RefEvent::Created, id);
+        #[cfg(feature = "event-log")]
+        inner.shared.events.record(EventKind::Created, Some(id));
 
-    #[test]
-    fn drop_wait_poll() {
-        let (task, wait) = super::awaitdrop();
+        if inner.shared.watch.is_watched() {
+            inner.shared.watch.created(Arc::strong_count(&inner));
+        }
 
-        drop(task);
+        Ref {
+            inner: Some(inner),
+            handle,
+            #[cfg(feature = "debug-tracking")]
+            location: Location::caller(),
+            #[cfg(feature = "hooks")]
+            context,
+        }
+    }
 
-        let fut = wait.wait();
+    /// Like [Ref::created], but gives up `inner` again if it takes the group
+    /// over its quota.
+    ///
+    /// The count is checked after incrementing it, so concurrent attempts can
+    /// never overshoot the quota, though they may all fail when only one of
+    /// them would have had to. A count given up that way is reported to the
+    /// watch like any other, since it may have hidden a drop in the meantime.
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    fn admitted(inner: Arc<Inner>) -> Option<Ref> {
+        let quota = inner.shared.quota.load(Ordering::Relaxed);
+        if quota != 0 && Arc::strong_count(&inner) > quota {
+            inner.release(false);
+            return None;
+        }
+        // Pairs with the fence in `try_unwrap_last`: either it sees our
+        // increment, or we see its seal.
+        atomic::fence(Ordering::SeqCst);
+        let shared = &inner.shared;
+        if shared.sealing.load(Ordering::Relaxed) != 0 || shared.released.load(Ordering::Acquire) {
+            inner.release(false);
+            return None;
+        }
+        Some(Ref::created(inner))
+    }
 
-        block_on(fut);
+    fn inner(&self) -> &Arc<Inner> {
+        self.inner.as_ref().unwrap()
     }
 
-    #[test]
-    fn wait_drop_poll() {
-        let (task, wait) = super::awaitdrop();
+    /// Get the source location this [Ref] was created at
+    ///
+    /// This is where the [Ref] was cloned, upgraded, or created along with its
+    /// group, as seen from outside the crate.
+    #[cfg(feature = "debug-tracking")]
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
 
-        let fut = wait.wait();
+    /// Check whether this is the only live [Ref] in its group
+    ///
+    /// Nobody else holds a [Ref] to clone, but a [Weak] may still be upgraded
+    /// or a [Waiter::acquire] complete right after this returns. Use
+    /// [Ref::try_unwrap_last] to rule that out.
+    pub fn is_last(&self) -> bool {
+        Arc::strong_count(self.inner()) == 1
+    }
 
-        drop(task);
+    /// Make sure this is the final [Ref] in its group
+    ///
+    /// On success, the [Ref] is handed back with the group sealed:
+    /// [Weak::upgrade], [Ref::try_ref] and [Waiter::acquire] all fail from then
+    /// on. Otherwise it's handed back as an error. Upgrades attempted while the
+    /// check is running may fail spuriously.
+    ///
+    /// Only those are blocked. The [Ref] itself can still be cloned, made
+    /// [scoped](Ref::scoped), [entered](Ref::enter) for [Ref::current], or
+    /// given to an [IdGroup], and any [Ref]s made that way keep the group alive
+    /// as usual. Whatever it does before being dropped is only done exactly
+    /// once as long as it does none of those.
+    pub fn try_unwrap_last(self) -> Result<Ref, Ref> {
+        let shared = &self.inner().shared;
+        shared.sealing.fetch_add(1, Ordering::Relaxed);
+        atomic::fence(Ordering::SeqCst);
+        if self.is_last() {
+            Ok(self)
+        } else {
+            shared.sealing.fetch_sub(1, Ordering::Relaxed);
+            Err(self)
+        }
+    }
 
-        block_on(fut);
+    /// Get the context attached to this [Ref] when it was created, if any
+    ///
+    /// See [Waiter::set_interceptor].
+    #[cfg(feature = "hooks")]
+    pub fn context(&self) -> Option<&(dyn Any + Send + Sync)> {
+        self.context.as_deref()
     }
 
-    #[test]
-    fn wait_poll_drop() {
-        let (task, wait) = super::awaitdrop();
+    /// Keep this [Ref] alive for the rest of the process, on purpose
+    ///
+    /// The group will never drain. Unlike [mem::forget], the [Ref] and `reason`
+    /// stay visible through [Waiter::leaked], so deliberate members like a
+    /// listener that runs forever can be told apart from actual leaks.
+    pub fn leak(self, reason: &'static str) -> RefId {
+        let id = self.id();
+        let shared = self.inner().shared.clone();
+        shared.leaked.lock().push((reason, self));
+        id
+    }
 
-        let start = time::Instant::now();
+    /// Check whether a [Waiter] has asked for the group to drain
+    ///
+    /// See [Waiter::request_drain].
+    pub fn drain_requested(&self) -> bool {
+        self.inner().shared.drain_requested.load(Ordering::Acquire)
+    }
 
-        thread::spawn(move || {
-            thread::sleep(Duration::from_secs(2));
-            drop(task);
-        });
+    /// Get this [Ref]'s unique identifier
+    ///
+    /// Every [Ref] gets a fresh id when it's created, including by cloning.
+    pub fn id(&self) -> RefId {
+        RefId {
+            group: self.inner().id,
+            handle: self.handle,
+        }
+    }
 
-        let fut = wait.wait();
+    /// Create a new [Ref] in the same group, unless it's already at its
+    /// [quota](Waiter::set_quota)
+    ///
+    /// Plain [Clone] always succeeds, even over the quota.
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn try_ref(&self) -> Option<Ref> {
+        let inner = self.inner();
+        if !inner.shared.admits(Arc::strong_count(inner)) {
+            return None;
+        }
+        Ref::admitted(inner.clone())
+    }
 
-        block_on(fut);
+    /// Get a new [Weak] that doesn't contribute to the ref count.
+    pub fn downgrade(&self) -> Weak {
+        Weak {
+            inner: Arc::downgrade(self.inner()),
+        }
+    }
 
-        assert!(time::Instant::now() - start > Duration::from_secs(2));
+    /// Downgrade each of `refs`, keeping their positions
+    ///
+    /// The [Ref]s are released as they're downgraded, so the group may drain
+    /// before this returns.
+    pub fn downgrade_all(refs: impl IntoIterator<Item = Ref>) -> Vec<Weak> {
+        refs.into_iter().map(Weak::from).collect()
     }
 
-    #[test]
-    fn wait_poll_drop_lots() {
-        let (task, wait) = super::awaitdrop();
+    /// Tie a value to this [Ref], so that its group won't drain until the
+    /// value is dropped
+    pub fn guard<T>(self, value: T) -> Guard<T> {
+        Guard::new(value, self)
+    }
 
-        let start = time::Instant::now();
+    /// Get a [CountObserver] for this [Ref]'s group
+    pub fn observer(&self) -> CountObserver {
+        CountObserver {
+            count: Arc::downgrade(self.inner()),
+        }
+    }
 
-        for _ in 0..20 {
-            let task = task.clone();
-            thread::spawn({
-                move || {
-                    thread::sleep(Duration::from_secs(2));
-                    drop(task);
-                }
-            });
+    /// Run `f` with a new [Ref] held for exactly as long as it runs
+    ///
+    /// The [Ref] is dropped on the way out, even if `f` panics.
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _scoped = self.clone();
+        f()
+    }
+
+    /// Consume this [Ref] and get a [Waiter] for all of the remaining ones.
+    ///
+    /// The [Waiter] is attached before this reference is released, so there's
+    /// no window in which it could miss the final drop.
+    pub fn detach_to_waiter(self) -> Waiter {
+        let waiter = Waiter::new(self.inner().shared.clone(), Arc::downgrade(self.inner()));
+        drop(self);
+        waiter
+    }
+}
+
+impl Clone for Ref {
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    fn clone(&self) -> Self {
+        Ref::created(self.inner().clone())
+    }
+}
+
+impl From<Ref> for Weak {
+    fn from(r: Ref) -> Weak {
+        r.downgrade()
+    }
+}
+
+impl TryFrom<Weak> for Ref {
+    type Error = Weak;
+
+    /// Upgrade the [Weak], handing it back if the group has drained.
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    fn try_from(weak: Weak) -> Result<Ref, Weak> {
+        weak.upgrade().ok_or(weak)
+    }
+}
+
+impl Drop for Ref {
+    fn drop(&mut self) {
+        let id = self.id();
+        let inner = self.inner.take().unwrap();
+
+        if thread::panicking() {
+            let shared = &inner.shared;
+            shared.panicked.lock().get_or_insert(id);
+            // Let anything failing fast know now, rather than on the last drop.
+            wake_all(&shared.fail_fast_wakers);
         }
 
-        drop(task);
+        #[cfg(feature = "hooks")]
+        inner.shared.hook.emit(RefEvent::Dropped, id);
+        #[cfg(feature = "event-log")]
+        inner.shared.events.record(EventKind::Dropped, Some(id));
 
-        let fut = wait.wait();
+        inner.release(true);
+    }
+}
 
-        block_on(fut);
+/// A lightweight handle that can only observe the number of live [Ref]s in a
+/// group
+///
+/// Unlike a [Waiter], it doesn't keep the group's waker storage alive.
+#[derive(Clone)]
+pub struct CountObserver {
+    count: sync::Weak<Inner>,
+}
 
-        assert!(time::Instant::now() - start > Duration::from_secs(2));
+impl CountObserver {
+    /// Get the number of live [Ref]s
+    pub fn count(&self) -> usize {
+        self.count.strong_count()
+    }
+
+    /// Check whether every [Ref] has been dropped
+    pub fn is_drained(&self) -> bool {
+        self.count() == 0
+    }
+}
+
+/// An awaitable handle to some number of references that will eventually be
+/// dropped
+pub struct Waiter {
+    shared: Arc<Shared>,
+    count: sync::Weak<Inner>,
+}
+
+impl Clone for Waiter {
+    fn clone(&self) -> Self {
+        Waiter::new(self.shared.clone(), self.count.clone())
+    }
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        let last = self.shared.waiters.fetch_sub(1, Ordering::AcqRel) == 1;
+        if last && self.count.strong_count() != 0 {
+            let callback = self.shared.abandoned.lock().take();
+            if let Some(callback) = callback {
+                callback()
+            }
+        }
+    }
+}
+
+impl Waiter {
+    fn new(shared: Arc<Shared>, count: sync::Weak<Inner>) -> Waiter {
+        shared.waiters.fetch_add(1, Ordering::AcqRel);
+        Waiter { shared, count }
+    }
+
+    /// Wake this group's [WaitFuture]s from a task on `spawner` instead of in
+    /// the final [Ref]'s drop
+    ///
+    /// By default, the thread dropping the last [Ref] wakes every waiting
+    /// future itself, which is quickest but can be expensive when there are
+    /// thousands. Deferring the wakes keeps that drop cheap. If the spawner
+    /// fails, the wakes happen inline after all.
+    pub fn defer_wakes(&self, spawner: impl Spawn + Send + Sync + 'static) {
+        *self.shared.wake_spawner.lock() = Some(Arc::new(spawner));
+    }
+
+    /// Go back to waking [WaitFuture]s in the final [Ref]'s drop
+    pub fn wake_inline(&self) {
+        self.shared.wake_spawner.lock().take();
+    }
+
+    /// Call `callback` if the last [Waiter] for this group is dropped while
+    /// [Ref]s remain
+    ///
+    /// Nothing would be left to notice the group draining at that point, which
+    /// is usually a bug. Wait futures count as [Waiter]s while they're alive.
+    /// Halves from [Waiter::into_parts] count as well, but dropping them
+    /// doesn't trigger the callback. It's called at most once, on the thread
+    /// that dropped the last [Waiter], and replaces any previous callback.
+    pub fn on_abandoned(&self, callback: impl FnOnce() + Send + 'static) {
+        *self.shared.abandoned.lock() = Some(Box::new(callback));
+    }
+
+    /// Wait for all connected [Ref]s to be dropped in a blocking manner
+    pub fn wait_blocking(&self) {
+        block_on(self.wait())
+    }
+
+    /// Wait for all connected [Ref]s to be dropped
+    pub fn wait(&self) -> WaitFuture {
+        self.clone().wait_owned()
+    }
+
+    /// Wait for all connected [Ref]s to be dropped, moving the [Waiter] into
+    /// the returned future
+    pub fn wait_owned(self) -> WaitFuture {
+        #[cfg(feature = "event-log")]
+        self.shared.events.record(EventKind::Wait, None);
+
+        WaitFuture {
+            idx: None,
+            waiter: self,
+            #[cfg(feature = "debug-tracking")]
+            pending_polls: 0,
+        }
+    }
+
+    /// Check whether every connected [Ref] has been dropped, without
+    /// registering a waker
+    ///
+    /// See [WaitFuture::peek].
+    pub fn peek(&self) -> Poll<()> {
+        if self.is_done() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Stop waiting for this group, whether or not every [Ref] has been
+    /// dropped
+    ///
+    /// For the last resort after a grace period has run out, when the holders
+    /// can't be made to let go. Every [WaitFuture] resolves, now and from then
+    /// on, and no more [Ref]s can be acquired through [Weak::upgrade],
+    /// [Ref::try_ref] or [Waiter::acquire], which resolves to `None` instead.
+    /// Live [Ref]s are left alone. Their drops no longer matter to anyone
+    /// waiting, though a [CountObserver] still counts them.
+    pub fn force_release(&self) {
+        self.shared.released.store(true, Ordering::Release);
+        let mut wakers = self.shared.take_final_wakers();
+        // Pending acquires are registered with the watch.
+        wakers.extend(self.shared.watch.take_wakers());
+        wake(wakers);
+    }
+
+    /// Check whether the group has drained or been
+    /// [force-released](Waiter::force_release)
+    fn is_done(&self) -> bool {
+        self.count.strong_count() == 0 || self.shared.released.load(Ordering::Acquire)
+    }
+
+    /// Have `waker` woken once every connected [Ref] has been dropped,
+    /// without going through a future
+    ///
+    /// For custom executors and foreign event loops. The waker is only woken
+    /// once the group has drained or been
+    /// [force-released](Waiter::force_release), never for anything in between,
+    /// and right away if that's already happened. The slot is kept until it's
+    /// given up with [Waiter::deregister_waker], so every call should be
+    /// paired with one.
+    pub fn register_waker(&self, waker: &Waker) -> WakerToken {
+        let idx = self.shared.raw_wakers.lock().insert(Some(waker.clone()));
+        if self.is_done() {
+            let waker = self
+                .shared
+                .raw_wakers
+                .lock()
+                .get_mut(idx)
+                .and_then(Option::take);
+            wake(waker);
+        }
+        WakerToken {
+            idx,
+            group: self.group_id(),
+        }
+    }
+
+    /// Give up a slot taken by [Waiter::register_waker]
+    ///
+    /// Tokens from other groups are ignored.
+    pub fn deregister_waker(&self, token: WakerToken) {
+        if token.group == self.group_id() {
+            self.shared.raw_wakers.lock().remove(token.idx);
+        }
+    }
+
+    /// Get a [CountObserver] for this [Waiter]'s group
+    pub fn observer(&self) -> CountObserver {
+        CountObserver {
+            count: self.count.clone(),
+        }
+    }
+
+    /// Reuse this [Waiter]'s group once it's finished with
+    ///
+    /// Succeeds if every [Ref] has been dropped and this is the last handle to
+    /// the group, returning a fresh [Ref] and [Waiter] that reuse its
+    /// allocations. Otherwise, the [Waiter] is handed back unchanged.
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn try_reuse(self) -> Result<(Ref, Waiter), Waiter> {
+        if self.count.strong_count() != 0 || Arc::strong_count(&self.shared) != 1 {
+            return Err(self);
+        }
+        let shared = self.shared.clone();
+        drop(self);
+        shared.reset();
+        Ok(group(shared))
+    }
+
+    /// Split this [Waiter] into its two halves
+    ///
+    /// Each half is a single pointer, so they can be stored separately, for
+    /// example one in a task-local and the other in a registry, and put back
+    /// together with [Waiter::from_parts].
+    pub fn into_parts(self) -> (CountHandle, WakerRegistry) {
+        self.shared.waiters.fetch_add(1, Ordering::AcqRel);
+        (
+            CountHandle(self.count.clone()),
+            WakerRegistry(self.shared.clone()),
+        )
+    }
+
+    /// Reassemble a [Waiter] from the halves produced by [Waiter::into_parts]
+    ///
+    /// Fails, handing the halves back, if they came from different groups.
+    pub fn from_parts(
+        count: CountHandle,
+        registry: WakerRegistry,
+    ) -> Result<Waiter, (CountHandle, WakerRegistry)> {
+        let same = sync::Weak::ptr_eq(&count.0, &*registry.0.count.lock());
+        if !same {
+            return Err((count, registry));
+        }
+        Ok(Waiter::new(registry.0.clone(), count.0.clone()))
+    }
+
+    /// Limit the number of live [Ref]s that [Weak::upgrade] and [Ref::try_ref]
+    /// will allow, or remove the limit with `None`
+    ///
+    /// Existing [Ref]s aren't affected, and [Ref::clone] ignores the quota.
+    ///
+    /// # Panics
+    ///
+    /// If the quota is zero.
+    pub fn set_quota(&self, quota: Option<usize>) {
+        assert_ne!(quota, Some(0), "quota must allow at least one Ref");
+        self.shared
+            .quota
+            .store(quota.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Get an approximation of the memory used by this group's shared state
+    ///
+    /// Covers the shared allocations, waker slots, registered callbacks, and
+    /// any feature-specific storage such as the event log. It doesn't include
+    /// the handles themselves.
+    pub fn memory_usage(&self) -> usize {
+        self.shared.memory_usage()
+    }
+
+    /// Ask the holders of this group's [Ref]s to wrap up and drop them
+    ///
+    /// This only sets a flag that [Ref::drain_requested] reports. It's up to
+    /// the holders to check it and stop cooperatively.
+    pub fn request_drain(&self) {
+        self.shared
+            .drain_requested_at
+            .lock()
+            .get_or_insert_with(Instant::now);
+        self.shared.drain_requested.store(true, Ordering::Release);
+    }
+
+    /// Get the [Ref]s that were dropped later than their
+    /// [deadline](Ref::with_drain_deadline), oldest first
+    ///
+    /// Violations are only recorded when the late [Ref] is dropped. [Ref]s
+    /// that are past their deadline but still alive aren't included, so this
+    /// can't be used to find the ones holding up a drain right now.
+    pub fn overdue(&self) -> Vec<OverdueRef> {
+        self.shared.overdue.lock().clone()
+    }
+
+    /// Get the [Ref]s that were deliberately kept alive with [Ref::leak],
+    /// along with the reasons given
+    pub fn leaked(&self) -> Vec<(RefId, &'static str)> {
+        self.shared
+            .leaked
+            .lock()
+            .iter()
+            .map(|(reason, r)| (r.id(), *reason))
+            .collect()
+    }
+
+    /// Get the number of times any of the group's [WaitFuture]s were polled
+    /// again without being ready
+    ///
+    /// The first pending poll of each future is how it registers, so it isn't
+    /// counted. Anything else is a wake that didn't need to happen, whether
+    /// from the group or from whatever else the task was waiting on.
+    #[cfg(feature = "debug-tracking")]
+    pub fn spurious_wakes(&self) -> u64 {
+        self.shared.spurious_wakes.load(Ordering::Relaxed)
+    }
+
+    /// Get the group's unique identifier
+    pub fn group_id(&self) -> GroupId {
+        let id = self.shared.id.load(Ordering::Relaxed);
+        GroupId(NonZeroU64::new(id).unwrap())
+    }
+
+    /// Get how long ago the group was created
+    ///
+    /// Returns `None` once every [Ref] has been dropped.
+    pub fn age(&self) -> Option<Duration> {
+        if self.count.strong_count() == 0 {
+            return None;
+        }
+        let started = (*self.shared.started.lock())?;
+        Some(started.elapsed())
+    }
+
+    /// Get how long it's been since a [Ref] in this group was last created or
+    /// dropped
+    ///
+    /// A group whose count hasn't moved in a long time may have had its [Ref]s
+    /// leaked, for example by [mem::forget] or a reference cycle, in which case
+    /// it will never drain. Returns `None` once every [Ref] has been dropped.
+    #[cfg(feature = "event-log")]
+    pub fn idle_for(&self) -> Option<Duration> {
+        if self.count.strong_count() == 0 {
+            return None;
+        }
+        let started = (*self.shared.started.lock())?;
+        let last = self.shared.events.last_change().unwrap_or(started);
+        Some(last.elapsed())
+    }
+
+    /// Get the most recent lifecycle events for this group, oldest first
+    ///
+    /// At most [EVENT_LOG_CAPACITY] events are kept.
+    #[cfg(feature = "event-log")]
+    pub fn events(&self) -> Vec<Event> {
+        self.shared.events.dump()
+    }
+
+    /// Register a hook to be called every time a [Ref] in this group is
+    /// created or dropped, along with that [Ref]'s id.
+    ///
+    /// Replaces any previously registered hook. The hook is called on the
+    /// thread performing the clone or drop, so it should be cheap.
+    #[cfg(feature = "hooks")]
+    pub fn set_hook(&self, hook: impl Fn(RefEvent, RefId) + Send + Sync + 'static) {
+        self.shared.hook.set(hook)
+    }
+
+    /// Register an interceptor to attach context to every new [Ref] in this
+    /// group
+    ///
+    /// The interceptor is called with the new [Ref]'s id whenever one is
+    /// created by cloning or upgrading, on the thread creating it. Whatever it
+    /// returns is available from [Ref::context]. Replaces any previously
+    /// registered interceptor.
+    #[cfg(feature = "hooks")]
+    pub fn set_interceptor(
+        &self,
+        interceptor: impl Fn(RefId) -> Option<RefContext> + Send + Sync + 'static,
+    ) {
+        self.shared.hook.set_interceptor(interceptor)
+    }
+}
+
+/// The half of a [Waiter] that tracks the number of live [Ref]s, from
+/// [Waiter::into_parts]
+pub struct CountHandle(sync::Weak<Inner>);
+
+/// The half of a [Waiter] that holds the group's waker storage, from
+/// [Waiter::into_parts]
+pub struct WakerRegistry(Arc<Shared>);
+
+impl Drop for WakerRegistry {
+    fn drop(&mut self) {
+        self.0.waiters.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Wait on an `Arc<Waiter>`, only cloning the [Waiter] if the [Arc] is still
+/// shared
+///
+/// A plain [Waiter] has [Waiter::wait_owned] for this.
+pub trait WaitOwned {
+    /// Wait for all connected [Ref]s to be dropped, moving the [Waiter] into
+    /// the returned future.
+    fn wait_owned(self) -> WaitFuture;
+}
+
+impl WaitOwned for Arc<Waiter> {
+    fn wait_owned(self) -> WaitFuture {
+        Arc::try_unwrap(self)
+            .unwrap_or_else(|a| (*a).clone())
+            .wait_owned()
+    }
+}
+
+/// A waker slot taken by [Waiter::register_waker]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WakerToken {
+    idx: DefaultKey,
+    group: GroupId,
+}
+
+/// The future returned from [Waiter::wait]
+///
+/// Resolves when all connected [Ref]s have been dropped.
+///
+/// Every [WaitFuture] gets its own waker slot the first time it's polled, and
+/// the final drop wakes all of them at once. Dropping one, whether before or
+/// after it's been woken, only gives up its own slot, so it can't cause any
+/// other to miss the notification.
+///
+/// Cloning a [WaitFuture] gives a new, independent one that will get its own
+/// slot when polled.
+///
+/// It's [Unpin], so a containing future can poll it with
+/// `Pin::new(&mut self.wait).poll(cx)`.
+pub struct WaitFuture {
+    idx: Option<DefaultKey>,
+    waiter: Waiter,
+    #[cfg(feature = "debug-tracking")]
+    pending_polls: u64,
+}
+
+impl Clone for WaitFuture {
+    fn clone(&self) -> Self {
+        WaitFuture {
+            idx: None,
+            waiter: self.waiter.clone(),
+            #[cfg(feature = "debug-tracking")]
+            pending_polls: 0,
+        }
+    }
+}
+
+impl Drop for WaitFuture {
+    fn drop(&mut self) {
+        if let Some(idx) = self.idx {
+            self.waiter.shared.wakers.lock().remove(idx);
+        }
+    }
+}
+
+impl WaitFuture {
+    /// Check whether the future is ready without registering a waker
+    ///
+    /// For state machines that poll on their own schedule. Nothing will wake
+    /// the caller when this changes, so it has to check again later.
+    pub fn peek(&self) -> Poll<()> {
+        self.waiter.peek()
+    }
+
+    /// Get the number of times this future has been polled without being
+    /// ready
+    ///
+    /// Ideally this is one: the poll that registered it. See
+    /// [Waiter::spurious_wakes].
+    #[cfg(feature = "debug-tracking")]
+    pub fn pending_polls(&self) -> u64 {
+        self.pending_polls
+    }
+}
+
+impl Future for WaitFuture {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        // Register before checking the count. The count hits zero before the
+        // final drop takes the lock to wake everyone, so either it sees our
+        // waker or we see the zero.
+        this.waiter
+            .shared
+            .wakers
+            .lock()
+            .register(&mut this.idx, cx.waker());
+        if this.waiter.is_done() {
+            return Poll::Ready(());
+        }
+
+        #[cfg(feature = "debug-tracking")]
+        {
+            // Every pending poll after the first was for nothing.
+            if this.pending_polls != 0 {
+                let shared = &this.waiter.shared;
+                shared.spurious_wakes.fetch_add(1, Ordering::Relaxed);
+            }
+            this.pending_polls += 1;
+        }
+        Poll::Pending
+    }
+}
+
+impl IntoFuture for Waiter {
+    type IntoFuture = WaitFuture;
+    type Output = ();
+    fn into_future(self) -> Self::IntoFuture {
+        self.wait_owned()
+    }
+}
+
+/// Create a new [Ref] and [Waiter]
+///
+/// The [Waiter] will resolve when the [Ref] and all clones of it have been
+/// dropped.
+#[cfg_attr(feature = "debug-tracking", track_caller)]
+pub fn awaitdrop() -> (Ref, Waiter) {
+    group(Default::default())
+}
+
+/// Create a new [Ref] and [Waiter] with room for `waiters` concurrent
+/// [WaitFuture]s
+///
+/// Behaves exactly like [awaitdrop], but allocates the waker storage up front
+/// so that the first `waiters` futures don't have to grow it.
+#[cfg_attr(feature = "debug-tracking", track_caller)]
+pub fn awaitdrop_with_capacity(waiters: usize) -> (Ref, Waiter) {
+    group(Arc::new(Shared {
+        wakers: Mutex::new(Wakers::with_capacity(waiters)),
+        ..Default::default()
+    }))
+}
+
+#[cfg_attr(feature = "debug-tracking", track_caller)]
+fn group(shared: Arc<Shared>) -> (Ref, Waiter) {
+    let id = GroupId::next();
+    shared.id.store(id.get(), Ordering::Relaxed);
+
+    *shared.started.lock() = Some(Instant::now());
+    let inner = Arc::new(Inner {
+        shared: shared.clone(),
+        id,
+    });
+    *shared.count.lock() = Arc::downgrade(&inner);
+    let wait = Waiter::new(shared, Arc::downgrade(&inner));
+
+    (
+        Ref {
+            inner: Some(inner),
+            handle: next_handle(),
+            #[cfg(feature = "debug-tracking")]
+            location: Location::caller(),
+            #[cfg(feature = "hooks")]
+            context: None,
+        },
+        wait,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        future::Future,
+        panic::{
+            self,
+            AssertUnwindSafe,
+        },
+        pin::Pin,
+        sync::{
+            atomic::{
+                AtomicUsize,
+                Ordering,
+            },
+            Arc,
+        },
+        task::Context,
+        thread,
+        time::{
+            self,
+            Duration,
+        },
+    };
+
+    use futures::{
+        executor::block_on,
+        task::{
+            self,
+            ArcWake,
+            FutureObj,
+            Spawn,
+            SpawnError,
+        },
+    };
+    use parking_lot::Mutex;
+
+    use super::WaitOwned;
+
+    #[test]
+    fn drop_wait_poll() {
+        let (task, wait) = super::awaitdrop();
+
+        drop(task);
+
+        let fut = wait.wait();
+
+        block_on(fut);
+    }
+
+    #[test]
+    fn wait_drop_poll() {
+        let (task, wait) = super::awaitdrop();
+
+        let fut = wait.wait();
+
+        drop(task);
+
+        block_on(fut);
+    }
+
+    #[test]
+    fn wait_poll_drop() {
+        let (task, wait) = super::awaitdrop();
+
+        let start = time::Instant::now();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_secs(2));
+            drop(task);
+        });
+
+        let fut = wait.wait();
+
+        block_on(fut);
+
+        assert!(time::Instant::now() - start > Duration::from_secs(2));
+    }
+
+    #[test]
+    fn wait_poll_drop_lots() {
+        let (task, wait) = super::awaitdrop();
+
+        let start = time::Instant::now();
+
+        for _ in 0..20 {
+            let task = task.clone();
+            thread::spawn({
+                move || {
+                    thread::sleep(Duration::from_secs(2));
+                    drop(task);
+                }
+            });
+        }
+
+        drop(task);
+
+        let fut = wait.wait();
+
+        block_on(fut);
+
+        assert!(time::Instant::now() - start > Duration::from_secs(2));
+    }
+
+    struct CountingWaker(AtomicUsize);
+
+    impl ArcWake for CountingWaker {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn drop_after_wake() {
+        let (task, wait) = super::awaitdrop();
+
+        let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = task::waker(wakes.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let mut first = wait.wait();
+        let mut second = wait.wait();
+        assert!(Pin::new(&mut first).poll(&mut cx).is_pending());
+        assert!(Pin::new(&mut second).poll(&mut cx).is_pending());
+
+        drop(task);
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 2);
+
+        drop(first);
+        assert!(Pin::new(&mut second).poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn panic_only_wakes_fail_fast() {
+        let (task, wait) = super::awaitdrop();
+        let crashed = task.clone();
+
+        let plain = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let plain_waker = task::waker(plain.clone());
+        let fast = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let fast_waker = task::waker(fast.clone());
+
+        let mut waiting = wait.wait();
+        let mut failing = wait.fail_fast();
+        assert!(Pin::new(&mut waiting)
+            .poll(&mut Context::from_waker(&plain_waker))
+            .is_pending());
+        assert!(Pin::new(&mut failing)
+            .poll(&mut Context::from_waker(&fast_waker))
+            .is_pending());
+
+        let _ = thread::spawn(move || {
+            let _crashed = crashed;
+            panic!("worker crashed");
+        })
+        .join();
+        assert_eq!(plain.0.load(Ordering::SeqCst), 0);
+        assert!(fast.0.load(Ordering::SeqCst) > 0);
+
+        drop(task);
+        assert_eq!(plain.0.load(Ordering::SeqCst), 1);
+    }
+
+    struct PanickingWaker;
+
+    impl ArcWake for PanickingWaker {
+        fn wake_by_ref(_: &Arc<Self>) {
+            panic!("bad waker");
+        }
+    }
+
+    #[test]
+    fn panicking_waker() {
+        let (task, wait) = super::awaitdrop();
+
+        let bad = task::waker(Arc::new(PanickingWaker));
+        let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let good = task::waker(wakes.clone());
+
+        let mut first = wait.wait();
+        let mut second = wait.wait();
+        let _ = Pin::new(&mut first).poll(&mut Context::from_waker(&bad));
+        let _ = Pin::new(&mut second).poll(&mut Context::from_waker(&good));
+
+        let res = panic::catch_unwind(AssertUnwindSafe(move || drop(task)));
+        assert!(res.is_err());
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn panicking_waker_watchers() {
+        let (task, wait) = super::awaitdrop();
+
+        let bad = task::waker(Arc::new(PanickingWaker));
+        let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let good = task::waker(wakes.clone());
+
+        let mut fut = wait.wait();
+        let _ = Pin::new(&mut fut).poll(&mut Context::from_waker(&bad));
+        let mut below = wait.wait_below(1);
+        assert!(Pin::new(&mut below)
+            .poll(&mut Context::from_waker(&good))
+            .is_pending());
+        let fired = Arc::new(AtomicUsize::new(0));
+        let counter = fired.clone();
+        wait.on_below(1, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let res = panic::catch_unwind(AssertUnwindSafe(move || drop(task)));
+        assert!(res.is_err());
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+        assert_eq!(fired.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn drop_during_wake() {
+        for _ in 0..100 {
+            let (task, wait) = super::awaitdrop();
+
+            let waiters = (0..8)
+                .map(|i| {
+                    let wait = wait.clone();
+                    thread::spawn(move || {
+                        let fut = wait.wait();
+                        if i % 2 == 0 {
+                            drop(fut);
+                        } else {
+                            block_on(fut);
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            drop(task);
+
+            for waiter in waiters {
+                waiter.join().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn drain_requested() {
+        let (task, wait) = super::awaitdrop();
+
+        let worker = thread::spawn(move || {
+            while !task.drain_requested() {
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+
+        wait.request_drain();
+        block_on(wait.wait());
+        worker.join().unwrap();
+    }
+
+    // Heavy tests meant to be run on their own, ideally under a sanitizer:
+    // cargo test -- --ignored stress
+
+    #[test]
+    #[ignore]
+    fn stress_clone_drop_wait() {
+        let deadline = time::Instant::now() + Duration::from_secs(3);
+        let mut rounds = 0;
+        while time::Instant::now() < deadline {
+            let (task, wait) = super::awaitdrop();
+            let waiters = (0..4)
+                .map(|_| {
+                    let wait = wait.clone();
+                    thread::spawn(move || block_on(wait.wait()))
+                })
+                .collect::<Vec<_>>();
+            let workers = (0..16)
+                .map(|_| {
+                    let task = task.clone();
+                    thread::spawn(move || {
+                        for _ in 0..100 {
+                            let refs = (0..4).map(|_| task.clone()).collect::<Vec<_>>();
+                            let weak = refs[0].downgrade();
+                            drop(refs);
+                            drop(weak.upgrade());
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            drop(task);
+
+            for worker in workers {
+                worker.join().unwrap();
+            }
+            for waiter in waiters {
+                waiter.join().unwrap();
+            }
+            assert!(wait.peek().is_ready());
+            rounds += 1;
+        }
+        assert!(rounds > 0);
+    }
+
+    #[test]
+    #[cfg_attr(not(miri), ignore)]
+    fn stress_watchers() {
+        // Miri is far too slow for the full run, but a few rounds still check
+        // the orderings.
+        let (iters, threads) = if cfg!(miri) { (4, 4) } else { (2000, 32) };
+        for _ in 0..iters {
+            let (task, wait) = super::awaitdrop();
+            let refs = (0..threads).map(|_| task.clone()).collect::<Vec<_>>();
+            let below = wait.wait_below(1);
+            let any = wait.wait_any_drop();
+            let droppers = refs
+                .into_iter()
+                .map(|r| thread::spawn(move || drop(r)))
+                .collect::<Vec<_>>();
+            drop(task);
+
+            block_on(any);
+            block_on(below);
+            for dropper in droppers {
+                dropper.join().unwrap();
+            }
+
+            // Cross from 2 to 1 while other threads churn the count with
+            // upgrades, some of which fail over the quota, and read the age.
+            let (task, wait) = super::awaitdrop();
+            wait.set_quota(Some(2));
+            let other = task.clone();
+            let weak = task.downgrade();
+            let crossed = Arc::new(AtomicUsize::new(0));
+            let fired = Arc::new(AtomicUsize::new(0));
+            wait.on_below(2, {
+                let (crossed, fired) = (crossed.clone(), fired.clone());
+                move || {
+                    assert_eq!(crossed.load(Ordering::SeqCst), 1, "fired at 2");
+                    fired.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+            let stop = Arc::new(AtomicUsize::new(0));
+            let churn = (0..2)
+                .map(|_| {
+                    let (weak, wait, stop) = (weak.clone(), wait.clone(), stop.clone());
+                    thread::spawn(move || {
+                        while stop.load(Ordering::SeqCst) == 0 {
+                            drop(weak.upgrade());
+                            assert!(wait.age().is_some());
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            crossed.store(1, Ordering::SeqCst);
+            drop(other);
+            stop.store(1, Ordering::SeqCst);
+            for churner in churn {
+                churner.join().unwrap();
+            }
+            assert_eq!(fired.load(Ordering::SeqCst), 1);
+            assert_eq!(wait.observer().count(), 1);
+            drop(task);
+        }
+    }
+
+    #[test]
+    fn parts() {
+        let (task, wait) = super::awaitdrop();
+        let (_other_task, other_wait) = super::awaitdrop();
+
+        let (count, registry) = wait.into_parts();
+        let (_, other_registry) = other_wait.into_parts();
+        let (count, _) = super::Waiter::from_parts(count, other_registry)
+            .map(drop)
+            .unwrap_err();
+        let wait = super::Waiter::from_parts(count, registry).ok().unwrap();
+
+        let fut = wait.wait();
+        drop(task);
+        block_on(fut);
+
+        // A drained count still only fits its own group.
+        let (live_task, live_wait) = super::awaitdrop();
+        let (count, _) = wait.into_parts();
+        let (_, live_registry) = live_wait.into_parts();
+        assert!(super::Waiter::from_parts(count, live_registry).is_err());
+        drop(live_task);
+    }
+
+    #[test]
+    fn last_ref() {
+        let (task, wait) = super::awaitdrop();
+        let other = task.clone();
+        let weak = task.downgrade();
+        assert!(!task.is_last());
+
+        let task = task.try_unwrap_last().err().unwrap();
+        assert!(weak.upgrade().is_some());
+        drop(other);
+        assert!(task.is_last());
+
+        let task = task.try_unwrap_last().ok().unwrap();
+        assert!(weak.upgrade().is_none());
+        assert!(wait.peek().is_pending());
+        drop(task);
+        assert!(wait.peek().is_ready());
+    }
+
+    #[test]
+    fn single_waiter_inline() {
+        let (task, wait) = super::awaitdrop();
+        let mut cx = Context::from_waker(task::noop_waker_ref());
+
+        let mut fut = wait.wait();
+        assert!(Pin::new(&mut fut).poll(&mut cx).is_pending());
+        assert_eq!(wait.shared.wakers.lock().memory_usage(), 0);
+
+        let mut second = wait.wait();
+        assert!(Pin::new(&mut second).poll(&mut cx).is_pending());
+        assert!(wait.shared.wakers.lock().memory_usage() > 0);
+
+        drop(task);
+        block_on(fut);
+        block_on(second);
+    }
+
+    #[test]
+    fn everything_unpin() {
+        fn unpin<T: Unpin>() {}
+
+        unpin::<super::Ref>();
+        unpin::<super::Waiter>();
+        unpin::<super::WaitFuture>();
+        unpin::<super::DrainFuture>();
+        unpin::<super::DoneSignal>();
+        unpin::<super::MapDone<fn()>>();
+        unpin::<super::OrElse<std::future::Pending<()>>>();
+        unpin::<super::AnyDropFuture>();
+        unpin::<super::BelowFuture>();
+        unpin::<super::AboveFuture>();
+        unpin::<super::AcquireFuture>();
+        unpin::<super::DrainStream<futures::stream::Pending<()>>>();
+        unpin::<super::WhileAlive<futures::stream::Pending<()>>>();
+        unpin::<super::Admit<futures::sink::Drain<super::Guard<()>>>>();
+    }
+
+    #[cfg(feature = "debug-tracking")]
+    #[test]
+    fn locations() {
+        let (task, _wait) = super::awaitdrop();
+        let line = line!() - 1;
+        assert_eq!(task.location().line(), line);
+        assert_eq!(task.location().file(), file!());
+
+        let other = task.clone();
+        assert_eq!(other.location().line(), line!() - 1);
+        let upgraded = task.downgrade().upgrade().unwrap();
+        assert_eq!(upgraded.location().line(), line!() - 1);
+    }
+
+    #[cfg(feature = "debug-tracking")]
+    #[test]
+    fn spurious_wakes() {
+        let (task, wait) = super::awaitdrop();
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut fut = wait.wait();
+        for _ in 0..3 {
+            assert!(Pin::new(&mut fut).poll(&mut cx).is_pending());
+        }
+        let mut other = fut.clone();
+        assert!(Pin::new(&mut other).poll(&mut cx).is_pending());
+
+        assert_eq!(fut.pending_polls(), 3);
+        assert_eq!(other.pending_polls(), 1);
+        assert_eq!(wait.spurious_wakes(), 2);
+
+        drop(task);
+        assert!(Pin::new(&mut fut).poll(&mut cx).is_ready());
+        assert_eq!(wait.spurious_wakes(), 2);
+    }
+
+    #[test]
+    fn raw_wakers() {
+        let (task, wait) = super::awaitdrop();
+        let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = task::waker(wakes.clone());
+
+        let token = wait.register_waker(&waker);
+        let removed = wait.register_waker(&waker);
+        wait.deregister_waker(removed);
+        let (_other, other_wait) = super::awaitdrop();
+        other_wait.deregister_waker(token);
+
+        drop(task);
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+        wait.deregister_waker(token);
+
+        let token = wait.register_waker(&waker);
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 2);
+        wait.deregister_waker(token);
+        assert!(wait.shared.raw_wakers.lock().is_empty());
+    }
+
+    #[test]
+    fn stale_waker_token() {
+        let (task, wait) = super::awaitdrop();
+        let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = task::waker(wakes.clone());
+
+        let stale = wait.register_waker(&waker);
+        wait.deregister_waker(stale);
+        let token = wait.register_waker(&waker);
+        // The slot may have been handed out again, but not to the old token.
+        wait.deregister_waker(stale);
+
+        drop(task);
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+        wait.deregister_waker(token);
+    }
+
+    #[test]
+    fn raw_wakers_after_panic() {
+        let (task, wait) = super::awaitdrop();
+        let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let token = wait.register_waker(&task::waker(wakes.clone()));
+
+        // A crashed Ref wakes wait futures early, but not raw wakers.
+        let crashed = task.clone();
+        let _ = thread::spawn(move || {
+            let _crashed = crashed;
+            panic!("worker crashed");
+        })
+        .join();
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 0);
+
+        drop(task);
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+        wait.deregister_waker(token);
+    }
+
+    #[test]
+    fn bulk_downgrade_upgrade() {
+        let (task, _wait) = super::awaitdrop();
+        let refs = (0..4).map(|_| task.clone()).collect::<Vec<_>>();
+        let weaks = super::Ref::downgrade_all(refs);
+        assert_eq!(weaks.len(), 4);
+        assert_eq!(task.observer().count(), 1);
+
+        let refs = super::Weak::upgrade_all(&weaks);
+        assert!(refs.iter().all(Option::is_some));
+        assert_eq!(task.observer().count(), 5);
+
+        drop(refs);
+        drop(task);
+        assert!(super::Weak::upgrade_all(&weaks).iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn deferred_wakes() {
+        // Hold on to spawned tasks until the test runs them.
+        #[derive(Clone, Default)]
+        struct Queue(Arc<Mutex<Vec<FutureObj<'static, ()>>>>);
+        impl Spawn for Queue {
+            fn spawn_obj(&self, future: FutureObj<'static, ()>) -> Result<(), SpawnError> {
+                self.0.lock().push(future);
+                Ok(())
+            }
+        }
+
+        let (task, wait) = super::awaitdrop();
+        let queue = Queue::default();
+        wait.defer_wakes(queue.clone());
+
+        let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = task::waker(wakes.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = wait.wait();
+        assert!(Pin::new(&mut fut).poll(&mut cx).is_pending());
+
+        drop(task);
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 0);
+        for task in queue.0.lock().drain(..) {
+            block_on(task);
+        }
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+        assert!(Pin::new(&mut fut).poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn abandoned() {
+        let (task, wait) = super::awaitdrop();
+        let fired = Arc::new(AtomicUsize::new(0));
+        wait.on_abandoned({
+            let fired = fired.clone();
+            move || {
+                fired.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        let fut = wait.clone().wait_owned();
+        drop(wait);
+        assert_eq!(fired.load(Ordering::SeqCst), 0);
+        drop(fut);
+        assert_eq!(fired.load(Ordering::SeqCst), 1);
+
+        let wait = task.detach_to_waiter();
+        drop(wait);
+        assert_eq!(fired.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn force_release() {
+        let (task, wait) = super::awaitdrop();
+        let weak = task.downgrade();
+        let fut = wait.wait();
+        assert!(wait.peek().is_pending());
+
+        wait.force_release();
+        block_on(fut);
+        block_on(wait.wait());
+        assert!(weak.upgrade().is_none());
+        assert!(task.try_ref().is_none());
+        assert_eq!(wait.observer().count(), 1);
+        drop(task);
+
+        let (task, wait) = super::awaitdrop();
+        wait.set_quota(Some(1));
+        let acquire = wait.acquire();
+        let mut sink = wait.admit(futures::sink::drain());
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            wait.force_release();
+        });
+        assert!(block_on(acquire).is_none());
+        assert_eq!(
+            block_on(futures::SinkExt::send(&mut sink, ())),
+            Err(super::AdmitError::Drained)
+        );
+        drop(task);
+    }
+
+    #[test]
+    fn peek() {
+        let (task, wait) = super::awaitdrop();
+        let fut = wait.wait();
+        assert!(fut.peek().is_pending());
+        assert!(wait.peek().is_pending());
+
+        drop(task);
+        assert!(fut.peek().is_ready());
+        assert!(wait.peek().is_ready());
+        // Peeking never allocated a waker slot.
+        assert_eq!(wait.shared.wakers.lock().memory_usage(), 0);
+    }
+
+    #[test]
+    fn drain_deadline() {
+        let (task, wait) = super::awaitdrop();
+        let slow = task.clone().with_drain_deadline(Duration::from_millis(50));
+        let fast = task.clone().with_drain_deadline(Duration::from_millis(50));
+        let untimed = super::awaitdrop().0;
+
+        drop(task);
+        wait.request_drain();
+        drop(fast);
+        thread::sleep(Duration::from_millis(100));
+        // Past its deadline, but not reported until it's gone.
+        assert!(wait.overdue().is_empty());
+        let slow_id = slow.tracker().id();
+        drop((slow, untimed));
+
+        let overdue = wait.overdue();
+        assert_eq!(overdue.len(), 1);
+        assert_eq!(overdue[0].id, slow_id);
+        assert!(overdue[0].elapsed > overdue[0].deadline);
+    }
+
+    #[test]
+    fn leak() {
+        let (task, wait) = super::awaitdrop();
+        let acceptor = task.clone();
+        let id = acceptor.leak("acceptor runs forever");
+        drop(task);
+
+        assert_eq!(wait.observer().count(), 1);
+        assert_eq!(wait.leaked(), [(id, "acceptor runs forever")]);
+    }
+
+    #[test]
+    fn age() {
+        let (task, wait) = super::awaitdrop();
+        thread::sleep(Duration::from_millis(50));
+        assert!(wait.age().unwrap() >= Duration::from_millis(50));
+        drop(task);
+        assert!(wait.age().is_none());
+    }
+
+    #[test]
+    fn with_capacity() {
+        let (task, wait) = super::awaitdrop_with_capacity(16);
+        let base = wait.shared.wakers.lock().memory_usage();
+
+        let mut cx = Context::from_waker(task::noop_waker_ref());
+        let mut futs = (0..16).map(|_| wait.wait()).collect::<Vec<_>>();
+        for fut in &mut futs {
+            assert!(Pin::new(fut).poll(&mut cx).is_pending());
+        }
+        assert_eq!(wait.shared.wakers.lock().memory_usage(), base);
+
+        drop(task);
+        for fut in futs {
+            block_on(fut);
+        }
+    }
+
+    #[test]
+    fn ref_ids() {
+        let (task, wait) = super::awaitdrop();
+        let other = task.clone();
+        let (unrelated, _other_wait) = super::awaitdrop();
+
+        assert_eq!(task.id().group, other.id().group);
+        assert_eq!(task.id().group, wait.group_id());
+        assert_ne!(task.id(), other.id());
+        assert_ne!(task.id().group, unrelated.id().group);
+        assert_eq!(
+            task.id().to_string(),
+            format!("{}:{}", task.id().group, task.id().handle)
+        );
+    }
+
+    #[test]
+    fn clone_wait_future() {
+        let (task, wait) = super::awaitdrop();
+
+        let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = task::waker(wakes.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let mut first = wait.wait();
+        assert!(Pin::new(&mut first).poll(&mut cx).is_pending());
+        let mut second = first.clone();
+        assert!(Pin::new(&mut second).poll(&mut cx).is_pending());
+        drop(first);
+
+        drop(task);
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+        assert!(Pin::new(&mut second).poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn detach_to_waiter() {
+        let (task, _wait) = super::awaitdrop();
+        let other = task.clone();
+
+        let wait = task.detach_to_waiter();
+        let fut = wait.wait();
+
+        drop(other);
+
+        block_on(fut);
+    }
+
+    #[test]
+    fn observer() {
+        let (task, wait) = super::awaitdrop();
+        let observer = wait.observer();
+        drop(wait);
+
+        let other = task.clone();
+        assert_eq!(observer.count(), 2);
+        drop(other);
+        assert_eq!(task.observer().count(), 1);
+        drop(task);
+        assert!(observer.is_drained());
+    }
+
+    #[test]
+    fn weak_counts() {
+        let (task, _wait) = super::awaitdrop();
+        let weak = task.downgrade();
+
+        let other = weak.upgrade().unwrap();
+        assert_eq!(weak.strong_count(), 2);
+        drop(other);
+        drop(task);
+        assert!(weak.is_dangling());
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn conversions() {
+        let (task, wait) = super::awaitdrop();
+        let other = task.clone();
+
+        let weak = super::Weak::from(task);
+        let task = super::Ref::try_from(weak).ok().unwrap();
+        let weak = super::Weak::from(task);
+        drop(other);
+
+        block_on(wait.wait());
+        assert!(super::Ref::try_from(weak).is_err());
+    }
+
+    #[test]
+    fn memory_usage() {
+        let (task, wait) = super::awaitdrop();
+
+        let base = wait.memory_usage();
+        assert!(base > 0);
+
+        let mut cx = Context::from_waker(task::noop_waker_ref());
+        let mut futs = (0..16).map(|_| wait.wait()).collect::<Vec<_>>();
+        for fut in &mut futs {
+            let _ = Pin::new(fut).poll(&mut cx);
+        }
+        assert!(wait.memory_usage() > base);
+
+        drop(futs);
+        drop(task);
+    }
+
+    #[test]
+    fn quota() {
+        let (task, wait) = super::awaitdrop();
+        wait.set_quota(Some(2));
+
+        let weak = task.downgrade();
+        let second = task.try_ref().unwrap();
+        assert!(task.try_ref().is_none());
+        assert!(weak.upgrade().is_none());
+
+        drop(second);
+        let second = weak.upgrade().unwrap();
+
+        wait.set_quota(None);
+        let third = task.try_ref().unwrap();
+        assert_eq!(wait.observer().count(), 3);
+
+        drop((task, second, third));
+        block_on(wait.wait());
+    }
+
+    #[test]
+    fn scope() {
+        let (task, wait) = super::awaitdrop();
+        let observer = wait.observer();
+
+        let count = task.scope(|| observer.count());
+        assert_eq!(count, 2);
+        assert_eq!(observer.count(), 1);
+    }
+
+    #[test]
+    fn try_reuse() {
+        let (task, wait) = super::awaitdrop();
+
+        let wait = wait.try_reuse().err().unwrap();
+        drop(task);
+        let other = wait.clone();
+        let wait = wait.try_reuse().err().unwrap();
+        drop(other);
+
+        let (task, wait) = wait.try_reuse().ok().unwrap();
+        let fut = wait.wait();
+        drop(task);
+        block_on(fut);
+    }
+
+    #[test]
+    fn reuse_forgets_watchers() {
+        let (task, wait) = super::awaitdrop();
+        let fired = Arc::new(AtomicUsize::new(0));
+        let counter = fired.clone();
+        wait.on_above(5, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        assert!(wait.shared.watch.is_watched());
+        drop(task);
+
+        let (task, wait) = wait.try_reuse().ok().unwrap();
+        assert!(!wait.shared.watch.is_watched());
+        let refs = (0..10).map(|_| task.clone()).collect::<Vec<_>>();
+        assert_eq!(fired.load(Ordering::SeqCst), 0);
+        drop(refs);
+    }
+
+    #[test]
+    fn wait_owned_arc() {
+        let (task, wait) = super::awaitdrop();
+        let wait = Arc::new(wait);
+
+        let fut = thread::spawn({
+            let wait = wait.clone();
+            move || block_on(wait.wait_owned())
+        });
+
+        drop(task);
+
+        fut.join().unwrap();
+        block_on(wait.wait_owned());
     }
 }
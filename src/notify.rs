@@ -0,0 +1,198 @@
+use std::{
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+        Waker,
+    },
+};
+
+use parking_lot::Mutex;
+use slotmap::DefaultKey;
+
+use crate::{
+    wake,
+    Wakers,
+};
+
+/// An async condition variable, built on the same waker storage as a group
+///
+/// Tasks wait on [Notify::notified], and are released by
+/// [Notify::notify_one] or [Notify::notify_all].
+#[derive(Default)]
+pub struct Notify {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    wakers: Wakers,
+    // Bumped by every `notify_all`. Futures created before the bump are done.
+    epoch: u64,
+    // Slots picked by `notify_one` whose futures haven't seen it yet.
+    chosen: Vec<DefaultKey>,
+    // A `notify_one` that came while nobody was waiting. At most one is kept.
+    permit: bool,
+}
+
+impl State {
+    fn notify_one(&mut self) -> Option<Waker> {
+        match self.wakers.take_one() {
+            Some((idx, waker)) => {
+                self.chosen.push(idx);
+                Some(waker)
+            }
+            None => {
+                self.permit = true;
+                None
+            }
+        }
+    }
+
+    fn take_chosen(&mut self, idx: DefaultKey) -> bool {
+        let pos = self.chosen.iter().position(|&chosen| chosen == idx);
+        pos.map(|pos| self.chosen.swap_remove(pos)).is_some()
+    }
+}
+
+impl Notify {
+    /// Create a new [Notify]
+    pub fn new() -> Notify {
+        Default::default()
+    }
+
+    /// Wait for a notification
+    ///
+    /// The future is woken by any [Notify::notify_all] that happens after it's
+    /// created, or by a [Notify::notify_one] that picks it.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            epoch: self.state.lock().epoch,
+            idx: None,
+            done: false,
+        }
+    }
+
+    /// Wake one waiting [Notified] future
+    ///
+    /// If none are waiting, the next one to be polled completes right away.
+    /// Notifications don't pile up: several calls with nobody waiting only
+    /// release a single future.
+    pub fn notify_one(&self) {
+        let waker = self.state.lock().notify_one();
+        wake(waker);
+    }
+
+    /// Wake every [Notified] future created so far
+    pub fn notify_all(&self) {
+        let wakers = {
+            let mut state = self.state.lock();
+            state.epoch += 1;
+            state.wakers.take_all().collect::<Vec<_>>()
+        };
+        wake(wakers);
+    }
+}
+
+/// The future returned from [Notify::notified]
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    epoch: u64,
+    idx: Option<DefaultKey>,
+    done: bool,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        if this.done {
+            return Poll::Ready(());
+        }
+
+        let mut state = this.notify.state.lock();
+        let chosen = matches!(this.idx, Some(idx) if state.take_chosen(idx));
+        let ready = chosen || state.epoch != this.epoch || mem::take(&mut state.permit);
+        if !ready {
+            state.wakers.register(&mut this.idx, cx.waker());
+            return Poll::Pending;
+        }
+
+        if let Some(idx) = this.idx.take() {
+            state.wakers.remove(idx);
+        }
+        this.done = true;
+        Poll::Ready(())
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let idx = match self.idx.take() {
+            Some(idx) => idx,
+            None => return,
+        };
+        let waker = {
+            let mut state = self.notify.state.lock();
+            state.wakers.remove(idx);
+            // Pass on a notify_one that picked us, so it isn't lost.
+            if state.take_chosen(idx) {
+                state.notify_one()
+            } else {
+                None
+            }
+        };
+        wake(waker);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{
+        executor::block_on,
+        FutureExt,
+    };
+
+    use super::Notify;
+
+    #[test]
+    fn notify_one() {
+        let notify = Notify::new();
+
+        // A notification with nobody waiting is kept for the next one.
+        notify.notify_one();
+        notify.notify_one();
+        block_on(notify.notified());
+        assert!(notify.notified().now_or_never().is_none());
+
+        let mut first = notify.notified();
+        let mut second = notify.notified();
+        assert!((&mut first).now_or_never().is_none());
+        assert!((&mut second).now_or_never().is_none());
+
+        notify.notify_one();
+        // Whichever one was picked, dropping it hands the notification on.
+        drop(first);
+        assert!((&mut second).now_or_never().is_some());
+    }
+
+    #[test]
+    fn notify_all() {
+        let notify = Notify::new();
+        let mut waits = (0..4).map(|_| notify.notified()).collect::<Vec<_>>();
+        for wait in &mut waits {
+            assert!(wait.now_or_never().is_none());
+        }
+
+        notify.notify_all();
+        let late = notify.notified();
+        for wait in waits {
+            block_on(wait);
+        }
+        assert!(late.now_or_never().is_none());
+    }
+}
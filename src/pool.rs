@@ -0,0 +1,108 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+};
+
+use parking_lot::Mutex;
+
+use crate::{
+    group,
+    Ref,
+    Shared,
+    Waiter,
+};
+
+/// A pool of recycled group allocations
+///
+/// Creating lots of short-lived groups with [awaitdrop](crate::awaitdrop)
+/// allocates fresh waker storage for every one of them. A [GroupPool] hangs on
+/// to the storage for up to `capacity` groups and hands it back out once every
+/// handle to the group that was using it has been dropped.
+pub struct GroupPool {
+    capacity: usize,
+    groups: Mutex<VecDeque<Arc<Shared>>>,
+}
+
+impl GroupPool {
+    /// Create a new pool that recycles up to `capacity` groups
+    pub fn new(capacity: usize) -> Self {
+        GroupPool {
+            capacity,
+            groups: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Create a new [Ref] and [Waiter], reusing a previous group's storage
+    /// if one is available
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn checkout(&self) -> (Ref, Waiter) {
+        let mut groups = self.groups.lock();
+
+        // Take the oldest group that's been released. Busy ones are skipped
+        // rather than waited on, so that one long-lived group can't hold up
+        // the rest.
+        let free = groups
+            .iter()
+            .position(|shared| Arc::strong_count(shared) == 1);
+        let shared = match free.and_then(|idx| groups.remove(idx)) {
+            Some(shared) => {
+                shared.reset();
+                shared
+            }
+            None => Default::default(),
+        };
+
+        if groups.len() < self.capacity {
+            groups.push_back(shared.clone());
+        }
+
+        group(shared)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    #[test]
+    fn reuses_released_groups() {
+        let pool = super::GroupPool::new(1);
+
+        let (task, wait) = pool.checkout();
+        let first = Arc::as_ptr(&task.inner().shared);
+        let first_id = wait.group_id();
+        drop(task);
+        wait.wait_blocking();
+        drop(wait);
+
+        let (task, wait) = pool.checkout();
+        assert_eq!(first, Arc::as_ptr(&task.inner().shared));
+        // Same allocation, but a new group.
+        assert_ne!(first_id, wait.group_id());
+
+        let (other, _other_wait) = pool.checkout();
+        assert_ne!(first, Arc::as_ptr(&other.inner().shared));
+
+        drop(task);
+        wait.wait_blocking();
+    }
+
+    #[test]
+    fn skips_busy_groups() {
+        let pool = super::GroupPool::new(2);
+
+        let (busy, _busy_wait) = pool.checkout();
+        let (task, wait) = pool.checkout();
+        let released = Arc::as_ptr(&task.inner().shared);
+        drop((task, wait));
+
+        // The busy group is first in line, but the released one behind it is
+        // still reused, again and again.
+        for _ in 0..5 {
+            let (task, wait) = pool.checkout();
+            assert_eq!(released, Arc::as_ptr(&task.inner().shared));
+            drop((task, wait));
+        }
+        drop(busy);
+    }
+}
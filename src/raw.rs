@@ -0,0 +1,73 @@
+use crate::{
+    Ref,
+    Waiter,
+};
+
+impl Ref {
+    /// Turn this [Ref] into a raw pointer, for passing through C callbacks or
+    /// `void *` userdata
+    ///
+    /// The [Ref] stays alive, and its group stays open, until the pointer is
+    /// passed to [Ref::from_raw]. Leaking the pointer leaks the [Ref].
+    pub fn into_raw(self) -> *const () {
+        Box::into_raw(Box::new(self)) as *const ()
+    }
+
+    /// Take back ownership of a [Ref] from a pointer made by [Ref::into_raw]
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have come from [Ref::into_raw], and must not have been passed
+    /// to this function already.
+    pub unsafe fn from_raw(ptr: *const ()) -> Ref {
+        *Box::from_raw(ptr as *mut Ref)
+    }
+}
+
+impl Waiter {
+    /// Turn this [Waiter] into a raw pointer, for passing through C callbacks
+    /// or `void *` userdata
+    ///
+    /// The [Waiter] stays alive until the pointer is passed to
+    /// [Waiter::from_raw]. Leaking the pointer leaks the [Waiter].
+    pub fn into_raw(self) -> *const () {
+        Box::into_raw(Box::new(self)) as *const ()
+    }
+
+    /// Take back ownership of a [Waiter] from a pointer made by
+    /// [Waiter::into_raw]
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have come from [Waiter::into_raw], and must not have been
+    /// passed to this function already.
+    pub unsafe fn from_raw(ptr: *const ()) -> Waiter {
+        *Box::from_raw(ptr as *mut Waiter)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::FutureExt;
+
+    use crate::{
+        Ref,
+        Waiter,
+    };
+
+    #[test]
+    fn round_trip() {
+        let (task, wait) = crate::awaitdrop();
+        let id = task.id();
+
+        let raw_task = task.into_raw();
+        let raw_wait = wait.into_raw();
+        let wait = unsafe { Waiter::from_raw(raw_wait) };
+        assert!(wait.wait().now_or_never().is_none());
+
+        let task = unsafe { Ref::from_raw(raw_task) };
+        assert_eq!(task.id(), id);
+        drop(task);
+        wait.wait().now_or_never().unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    marker::PhantomData,
+    thread::{
+        self,
+        Scope,
+        ScopedJoinHandle,
+    },
+};
+
+use crate::{
+    Ref,
+    RefId,
+};
+
+/// A scope for spawning tracked threads, created by [Ref::scope_blocking]
+pub struct ThreadScope<'scope, 'env> {
+    scope: &'scope Scope<'scope, 'env>,
+    tracker: &'scope Ref,
+}
+
+impl<'scope, 'env> ThreadScope<'scope, 'env> {
+    /// Spawn a scoped thread, handing it a new [Ref] in the group
+    ///
+    /// The thread may borrow from outside the scope, just like with
+    /// [Scope::spawn].
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn spawn<F, T>(&self, f: F) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce(Ref) -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let tracker = self.tracker.clone();
+        self.scope.spawn(move || f(tracker))
+    }
+}
+
+impl Ref {
+    /// Run `f` with a scope for spawning threads that each get a [Ref] in this
+    /// group
+    ///
+    /// Like [thread::scope], this only returns once every spawned thread has
+    /// finished. In the meantime, any [Waiter](crate::Waiter) for the group
+    /// can watch them wind down asynchronously.
+    pub fn scope_blocking<'env, F, T>(&'env self, f: F) -> T
+    where
+        F: for<'scope> FnOnce(&ThreadScope<'scope, 'env>) -> T,
+    {
+        thread::scope(|scope| {
+            f(&ThreadScope {
+                scope,
+                tracker: self,
+            })
+        })
+    }
+
+    /// Get a new [ScopedRef] in this group, tied to the lifetime of `self`
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn scoped(&self) -> ScopedRef<'_> {
+        ScopedRef {
+            tracker: self.clone(),
+            _borrow: PhantomData,
+        }
+    }
+}
+
+/// A [Ref] that can't outlive the borrow it was created from, returned by
+/// [Ref::scoped]
+///
+/// It counts towards the group like any other [Ref], but there's no way to
+/// turn it into one. A function that only takes a [ScopedRef] is guaranteed by
+/// the compiler not to keep the group alive past its caller's frame.
+///
+/// ```compile_fail
+/// fn escape(tracker: &awaitdrop::Ref) -> awaitdrop::ScopedRef<'static> {
+///     tracker.scoped()
+/// }
+/// ```
+pub struct ScopedRef<'a> {
+    tracker: Ref,
+    _borrow: PhantomData<&'a Ref>,
+}
+
+impl ScopedRef<'_> {
+    /// Get this handle's unique identifier
+    pub fn id(&self) -> RefId {
+        self.tracker.id()
+    }
+
+    /// Check whether a [Waiter](crate::Waiter) has asked for the group to
+    /// drain
+    ///
+    /// See [Ref::drain_requested].
+    pub fn drain_requested(&self) -> bool {
+        self.tracker.drain_requested()
+    }
+}
+
+impl Clone for ScopedRef<'_> {
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    fn clone(&self) -> Self {
+        ScopedRef {
+            tracker: self.tracker.clone(),
+            _borrow: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    };
+
+    use futures::FutureExt;
+
+    #[test]
+    fn scoped_ref() {
+        fn worker(tracker: crate::ScopedRef<'_>) -> usize {
+            let more = tracker.clone();
+            assert_ne!(more.id(), tracker.id());
+            assert!(!more.drain_requested());
+            2
+        }
+
+        let (task, wait) = crate::awaitdrop();
+        let scoped = task.scoped();
+        assert_eq!(wait.observer().count(), 2);
+        assert_eq!(worker(scoped), 2);
+        assert_eq!(wait.observer().count(), 1);
+    }
+
+    #[test]
+    fn scoped_threads() {
+        let (task, wait) = crate::awaitdrop();
+        let finished = AtomicUsize::new(0);
+
+        let observed = task.scope_blocking(|s| {
+            for _ in 0..4 {
+                s.spawn(|_tracker| {
+                    finished.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+            wait.observer().count()
+        });
+
+        assert!(observed >= 1);
+        assert_eq!(finished.load(Ordering::SeqCst), 4);
+        assert_eq!(wait.observer().count(), 1);
+        drop(task);
+        wait.wait().now_or_never().unwrap();
+    }
+
+    #[cfg(feature = "debug-tracking")]
+    #[test]
+    fn scoped_thread_location() {
+        let (task, _wait) = crate::awaitdrop();
+        let location =
+            task.scope_blocking(|s| s.spawn(|tracker| tracker.location()).join().unwrap());
+        assert_eq!(location.line(), line!() - 1);
+        assert_eq!(location.file(), file!());
+    }
+}
@@ -0,0 +1,289 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+};
+
+use futures::{
+    future::Shared,
+    FutureExt,
+};
+
+use crate::{
+    WaitFuture,
+    Waiter,
+};
+
+/// A cloneable future that resolves once every [Ref](crate::Ref) in a group
+/// has been dropped, created by [Waiter::done_shared]
+///
+/// All clones share a single waker slot in the group, so handing one to
+/// thousands of tasks costs the group no more than a single [WaitFuture].
+#[derive(Clone)]
+pub struct DoneSignal(Shared<WaitFuture>);
+
+impl Waiter {
+    /// Get a [DoneSignal] for this group
+    ///
+    /// Every clone of the signal resolves at the same time as [Waiter::wait]
+    /// would.
+    pub fn done_shared(&self) -> DoneSignal {
+        DoneSignal(self.wait().shared())
+    }
+
+    /// Wait for all connected [Ref](crate::Ref)s to be dropped, then resolve to
+    /// the result of `f`
+    ///
+    /// `f` runs at most once, on whichever task polls the future after the
+    /// group drains.
+    pub fn map_done<T, F: FnOnce() -> T>(&self, f: F) -> MapDone<F> {
+        MapDone {
+            wait: self.wait(),
+            f: Some(f),
+        }
+    }
+
+    /// Wait for the group to drain, escalating to `fallback` if `deadline`
+    /// resolves first
+    ///
+    /// `deadline` can be any future, such as a timer from whichever runtime is
+    /// in use. Once it fires, only `fallback` is waited on, and the result
+    /// says which group finished.
+    pub fn or_else<D: Future<Output = ()>>(&self, deadline: D, fallback: &Waiter) -> OrElse<D> {
+        OrElse {
+            primary: Some(self.wait()),
+            deadline: Box::pin(deadline),
+            fallback: fallback.wait(),
+        }
+    }
+
+    /// Wait for any one of `waiters` to drain
+    ///
+    /// Never resolves if `waiters` is empty.
+    pub fn first_of(waiters: impl IntoIterator<Item = Waiter>) -> FirstOf {
+        FirstOf {
+            waits: waiters.into_iter().map(Waiter::wait_owned).collect(),
+        }
+    }
+
+    /// Wait for every one of `waiters` to drain
+    ///
+    /// Resolves immediately if `waiters` is empty.
+    pub fn all_of(waiters: impl IntoIterator<Item = Waiter>) -> AllOf {
+        AllOf {
+            waits: waiters.into_iter().map(Waiter::wait_owned).collect(),
+        }
+    }
+}
+
+/// The future returned from [Waiter::map_done]
+pub struct MapDone<F> {
+    wait: WaitFuture,
+    f: Option<F>,
+}
+
+// The callback is never pinned, only moved out to be called.
+impl<F> Unpin for MapDone<F> {}
+
+impl<T, F: FnOnce() -> T> Future for MapDone<F> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        futures::ready!(Pin::new(&mut self.wait).poll(cx));
+        let f = self.f.take().expect("MapDone polled after completion");
+        Poll::Ready(f())
+    }
+}
+
+/// Which group an [OrElse] future ended up waiting for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escalation {
+    /// The primary group drained before the deadline
+    Primary,
+    /// The deadline passed, and then the fallback group drained
+    Fallback,
+}
+
+/// The future returned from [Waiter::or_else]
+pub struct OrElse<D> {
+    primary: Option<WaitFuture>,
+    deadline: Pin<Box<D>>,
+    fallback: WaitFuture,
+}
+
+impl<D: Future<Output = ()>> Future for OrElse<D> {
+    type Output = Escalation;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Escalation> {
+        let this = &mut *self;
+        if let Some(primary) = this.primary.as_mut() {
+            if Pin::new(primary).poll(cx).is_ready() {
+                return Poll::Ready(Escalation::Primary);
+            }
+            if this.deadline.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            // Give up the primary's waker slot, it's no longer of interest.
+            this.primary = None;
+        }
+        Pin::new(&mut this.fallback)
+            .poll(cx)
+            .map(|_| Escalation::Fallback)
+    }
+}
+
+/// The future returned from [Waiter::first_of]
+pub struct FirstOf {
+    waits: Vec<WaitFuture>,
+}
+
+impl Future for FirstOf {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        for wait in &mut self.waits {
+            if Pin::new(wait).poll(cx).is_ready() {
+                return Poll::Ready(());
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// The future returned from [Waiter::all_of]
+pub struct AllOf {
+    waits: Vec<WaitFuture>,
+}
+
+impl Future for AllOf {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // Drop the futures that are done as we go, giving up their waker slots.
+        self.waits
+            .retain_mut(|wait| Pin::new(wait).poll(cx).is_pending());
+        if self.waits.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl DoneSignal {
+    /// Check whether the signal has already fired
+    ///
+    /// This only reports what a previous poll of any clone has seen, so it may
+    /// lag behind the group itself.
+    pub fn is_done(&self) -> bool {
+        self.0.peek().is_some()
+    }
+}
+
+impl Future for DoneSignal {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        thread,
+        time::Duration,
+    };
+
+    use futures::{
+        executor::block_on,
+        FutureExt,
+    };
+
+    use super::Escalation;
+    use crate::Waiter;
+
+    #[test]
+    fn shared_signal() {
+        let (task, wait) = crate::awaitdrop();
+        let signal = wait.done_shared();
+        assert!(signal.clone().now_or_never().is_none());
+        let used = wait.memory_usage();
+
+        // All of the clones share the one waker slot.
+        let signals = (0..16).map(|_| signal.clone()).collect::<Vec<_>>();
+        for signal in &signals {
+            assert!(signal.clone().now_or_never().is_none());
+        }
+        assert_eq!(wait.memory_usage(), used);
+        assert!(!signal.is_done());
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(task);
+        });
+
+        for signal in signals {
+            block_on(signal);
+        }
+        assert!(signal.is_done());
+    }
+
+    #[test]
+    fn or_else() {
+        let (task, wait) = crate::awaitdrop();
+        let (killed, fallback) = crate::awaitdrop();
+        let (deadline, timer) = crate::awaitdrop();
+
+        let fut = wait.or_else(timer.wait(), &fallback);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(deadline);
+            thread::sleep(Duration::from_millis(50));
+            drop(killed);
+        });
+        assert_eq!(block_on(fut), Escalation::Fallback);
+        drop(task);
+
+        let (task, wait) = crate::awaitdrop();
+        drop(task);
+        let fut = wait.or_else(futures::future::pending(), &fallback);
+        assert_eq!(block_on(fut), Escalation::Primary);
+    }
+
+    #[test]
+    fn first_and_all_of() {
+        let (first, first_wait) = crate::awaitdrop();
+        let (second, second_wait) = crate::awaitdrop();
+        let waiters = [first_wait, second_wait];
+
+        let any = Waiter::first_of(waiters.iter().cloned());
+        let mut all = Waiter::all_of(waiters.iter().cloned());
+        drop(first);
+        block_on(any);
+        assert!((&mut all).now_or_never().is_none());
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(second);
+        });
+        block_on(all);
+        block_on(Waiter::all_of([]));
+    }
+
+    #[test]
+    fn map_done() {
+        let (task, wait) = crate::awaitdrop();
+        let fut = wait.map_done(|| "summary");
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(task);
+        });
+
+        assert_eq!(block_on(fut), "summary");
+    }
+}
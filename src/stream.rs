@@ -0,0 +1,163 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+};
+
+use futures::Stream;
+
+use crate::{
+    WaitFuture,
+    Waiter,
+};
+
+impl Waiter {
+    /// Yield items from `stream` until every connected [Ref](crate::Ref) has
+    /// been dropped and there's nothing left to receive
+    ///
+    /// Meant for queues fed by tracked producers, such as a channel whose
+    /// senders each hold a [Ref](crate::Ref). Anything sent before the last
+    /// producer's [Ref](crate::Ref) was dropped is still yielded. The returned
+    /// stream also ends if `stream` does.
+    pub fn drain_stream<S: Stream + Unpin>(&self, stream: S) -> DrainStream<S> {
+        DrainStream {
+            stream: Some(stream),
+            wait: self.wait(),
+        }
+    }
+
+    /// Yield items from `stream`, such as ticks from an interval timer, only
+    /// while the group has live [Ref](crate::Ref)s
+    ///
+    /// The returned stream ends as soon as the group drains, without waiting
+    /// for another item, or when `stream` itself ends.
+    pub fn while_alive<S: Stream + Unpin>(&self, stream: S) -> WhileAlive<S> {
+        WhileAlive {
+            stream: Some(stream),
+            wait: self.wait(),
+        }
+    }
+}
+
+/// The stream returned from [Waiter::drain_stream]
+pub struct DrainStream<S> {
+    stream: Option<S>,
+    wait: WaitFuture,
+}
+
+impl<S: Stream + Unpin> Stream for DrainStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        let this = &mut *self;
+        let stream = match this.stream.as_mut() {
+            Some(stream) => stream,
+            None => return Poll::Ready(None),
+        };
+
+        if let Poll::Ready(item) = Pin::new(&mut *stream).poll_next(cx) {
+            if item.is_none() {
+                this.stream = None;
+            }
+            return Poll::Ready(item);
+        }
+
+        if Pin::new(&mut this.wait).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        // An item may have been sent between checking the stream and the group
+        // draining, so look one more time now that nothing else can arrive.
+        let item = match Pin::new(&mut *stream).poll_next(cx) {
+            Poll::Ready(item) => item,
+            Poll::Pending => None,
+        };
+        if item.is_none() {
+            this.stream = None;
+        }
+        Poll::Ready(item)
+    }
+}
+
+/// The stream returned from [Waiter::while_alive]
+pub struct WhileAlive<S> {
+    stream: Option<S>,
+    wait: WaitFuture,
+}
+
+impl<S: Stream + Unpin> Stream for WhileAlive<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        let this = &mut *self;
+        let stream = match this.stream.as_mut() {
+            Some(stream) => stream,
+            None => return Poll::Ready(None),
+        };
+
+        // Check the group first, so that a ready item can't hold up the end.
+        if Pin::new(&mut this.wait).poll(cx).is_ready() {
+            this.stream = None;
+            return Poll::Ready(None);
+        }
+
+        let item = futures::ready!(Pin::new(stream).poll_next(cx));
+        if item.is_none() {
+            this.stream = None;
+        }
+        Poll::Ready(item)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        thread,
+        time::Duration,
+    };
+
+    use futures::{
+        channel::mpsc,
+        executor::block_on,
+        stream,
+        FutureExt,
+        StreamExt,
+    };
+
+    use crate::Guard;
+
+    #[test]
+    fn drains_after_producers() {
+        let (task, wait) = crate::awaitdrop();
+        // Keep a sender around so the channel itself never closes.
+        let (tx, rx) = mpsc::unbounded();
+
+        for i in 0..4 {
+            let producer = Guard::new(tx.clone(), task.clone());
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10 * i));
+                producer.unbounded_send(i).unwrap();
+            });
+        }
+        drop(task);
+
+        let mut items = block_on(wait.drain_stream(rx).collect::<Vec<_>>());
+        items.sort();
+        assert_eq!(items, [0, 1, 2, 3]);
+        drop(tx);
+    }
+
+    #[test]
+    fn while_alive() {
+        let (task, wait) = crate::awaitdrop();
+        let mut ticks = wait.while_alive(stream::repeat(()));
+
+        assert_eq!(block_on(ticks.next()), Some(()));
+        assert_eq!(block_on(ticks.next()), Some(()));
+        drop(task);
+        assert_eq!(ticks.next().now_or_never(), Some(None));
+    }
+}
@@ -0,0 +1,120 @@
+//! Helpers for testing shutdown logic built on this crate
+//!
+//! Enable the `testing` feature, usually only in `dev-dependencies`.
+
+use std::time::Duration;
+
+use crate::{
+    Ref,
+    Waiter,
+};
+
+/// Assert that a [Waiter]'s group drains within a timeout
+///
+/// ```
+/// # use std::time::Duration;
+/// # use awaitdrop::assert_drains;
+/// let (task, waiter) = awaitdrop::awaitdrop();
+/// drop(task);
+/// assert_drains!(waiter, within: Duration::from_secs(1));
+/// ```
+#[macro_export]
+macro_rules! assert_drains {
+    ($waiter:expr, within: $timeout:expr $(,)?) => {{
+        let timeout = $timeout;
+        assert!(
+            $crate::testing::drains_within(&$waiter, timeout),
+            "group did not drain within {:?}",
+            timeout,
+        );
+    }};
+}
+
+/// Block the current thread until `waiter`'s group drains or `timeout`
+/// passes, returning whether it drained
+///
+/// The same as [Waiter::drain_in_drop].
+pub fn drains_within(waiter: &Waiter, timeout: Duration) -> bool {
+    waiter.drain_in_drop(timeout)
+}
+
+/// A group whose count can be set directly
+///
+/// Holds the group's [Ref]s itself, so tests can move the count around without
+/// having to juggle handles.
+pub struct MockGroup {
+    refs: Vec<Ref>,
+    waiter: Waiter,
+}
+
+impl MockGroup {
+    /// Create a new group with a count of one
+    pub fn new() -> Self {
+        let (r, waiter) = crate::awaitdrop();
+        MockGroup {
+            refs: vec![r],
+            waiter,
+        }
+    }
+
+    /// Get a [Waiter] for the group
+    pub fn waiter(&self) -> &Waiter {
+        &self.waiter
+    }
+
+    /// Get the number of [Ref]s the mock is holding
+    pub fn count(&self) -> usize {
+        self.refs.len()
+    }
+
+    /// Create or drop [Ref]s until the mock holds `count` of them
+    ///
+    /// Setting it to zero drains the group for good. Raising it again after
+    /// that will panic.
+    pub fn set_count(&mut self, count: usize) {
+        if count > self.refs.len() {
+            let r = self.refs.first().expect("group has already drained");
+            let more = (self.refs.len()..count)
+                .map(|_| r.clone())
+                .collect::<Vec<_>>();
+            self.refs.extend(more);
+        } else {
+            self.refs.truncate(count);
+        }
+    }
+}
+
+impl Default for MockGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        thread,
+        time::Duration,
+    };
+
+    use super::{
+        drains_within,
+        MockGroup,
+    };
+
+    #[test]
+    fn mock_group() {
+        let mut group = MockGroup::new();
+        group.set_count(3);
+        assert_eq!(group.waiter().observer().count(), 3);
+        assert!(!drains_within(group.waiter(), Duration::from_millis(10)));
+
+        group.set_count(1);
+        let waiter = group.waiter().clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            group.set_count(0);
+        });
+        crate::assert_drains!(waiter, within: Duration::from_secs(5));
+    }
+}
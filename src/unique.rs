@@ -0,0 +1,77 @@
+use crate::{
+    Ref,
+    RefId,
+    Weak,
+};
+
+/// A [Ref] that can't be cloned
+///
+/// For places where an accidental clone would hide a lifecycle bug: each
+/// [UniqueRef] is exactly one member of the group, and it goes away when it's
+/// moved into whatever drops it. Converting to and from a plain [Ref] has to be
+/// done explicitly, with [Ref::into_unique] and [UniqueRef::into_ref].
+///
+/// ```compile_fail
+/// let (task, _wait) = awaitdrop::awaitdrop();
+/// let unique = task.into_unique();
+/// let copy = unique.clone();
+/// ```
+pub struct UniqueRef(Ref);
+
+impl Ref {
+    /// Turn this [Ref] into a [UniqueRef]
+    pub fn into_unique(self) -> UniqueRef {
+        UniqueRef(self)
+    }
+}
+
+impl UniqueRef {
+    /// Turn this back into a plain, cloneable [Ref]
+    pub fn into_ref(self) -> Ref {
+        self.0
+    }
+
+    /// Get this handle's unique identifier
+    pub fn id(&self) -> RefId {
+        self.0.id()
+    }
+
+    /// Check whether a [Waiter](crate::Waiter) has asked for the group to
+    /// drain
+    ///
+    /// See [Ref::drain_requested].
+    pub fn drain_requested(&self) -> bool {
+        self.0.drain_requested()
+    }
+
+    /// Get a new [Weak] that doesn't contribute to the ref count
+    pub fn downgrade(&self) -> Weak {
+        self.0.downgrade()
+    }
+}
+
+impl From<UniqueRef> for Ref {
+    fn from(unique: UniqueRef) -> Ref {
+        unique.into_ref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::FutureExt;
+
+    #[test]
+    fn round_trip() {
+        let (task, wait) = crate::awaitdrop();
+        let id = task.id();
+
+        let unique = task.into_unique();
+        assert_eq!(unique.id(), id);
+        assert_eq!(wait.observer().count(), 1);
+
+        let task = crate::Ref::from(unique);
+        assert_eq!(task.id(), id);
+        drop(task);
+        wait.wait().now_or_never().unwrap();
+    }
+}
@@ -0,0 +1,613 @@
+use std::{
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::{
+        self,
+        atomic::{
+            self,
+            AtomicBool,
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+    },
+    task::{
+        Context,
+        Poll,
+        Waker,
+    },
+};
+
+use parking_lot::{
+    const_mutex,
+    Mutex,
+};
+use slotmap::DefaultKey;
+
+use crate::{
+    resume,
+    wake_all,
+    wake_caught,
+    GroupId,
+    Ref,
+    Shared,
+    Waiter,
+    Wakers,
+};
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+// Bumped by every new watcher, so that a drop that found its group unwatched
+// can tell afterwards whether that might have changed in the meantime.
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+// Every live group that has had a watcher, so that such a drop can still find
+// its group's shared state after giving up the count that kept it alive.
+static WATCHED: Mutex<Vec<(GroupId, sync::Weak<Shared>)>> = const_mutex(Vec::new());
+
+/// Read the watcher epoch, before checking whether a group is watched.
+pub(crate) fn epoch() -> usize {
+    EPOCH.load(Ordering::Acquire)
+}
+
+/// Find the group `id` if a watcher has registered anywhere since `epoch`,
+/// after a count on it was given up without reporting to its watch.
+///
+/// Unwatched groups only pay for the fence and the load here, and the fence
+/// is free on most platforms.
+pub(crate) fn watched_since(epoch: usize, id: GroupId) -> Option<Arc<Shared>> {
+    // Pairs with the release fence in `Waiter::add_watcher`, through the
+    // count it takes after registering: if that came before our decrement, we
+    // see its epoch. Otherwise it saw our decrement.
+    atomic::fence(Ordering::Acquire);
+    if EPOCH.load(Ordering::Relaxed) == epoch {
+        return None;
+    }
+    WATCHED
+        .lock()
+        .iter()
+        .filter(|(watched, _)| *watched == id)
+        .find_map(|(_, shared)| shared.upgrade())
+}
+
+/// Bookkeeping for things that care about every change to the count, rather
+/// than only the final drop.
+///
+/// [Ref]s only report to it while `watchers` is non-zero, so unwatched groups
+/// don't pay for it. A drop that races with a new watcher registering is caught
+/// through [epoch] and [watched_since], so a watcher either hears about every
+/// drop or sees its effect on the count when it registers. A creation that
+/// races with a new watcher registering may go unreported, in which case the
+/// watcher finds out at the next change or when the group drains.
+#[derive(Default)]
+pub(crate) struct Watch {
+    watchers: AtomicUsize,
+    // Whether the group is in `WATCHED`.
+    tracked: AtomicBool,
+    drops: AtomicUsize,
+    wakers: Mutex<Wakers>,
+    below: Mutex<Vec<(usize, Callback)>>,
+    above: Mutex<Vec<(usize, Callback)>>,
+}
+
+impl Watch {
+    pub(crate) fn is_watched(&self) -> bool {
+        // Ordered by the epoch read before it.
+        self.watchers.load(Ordering::Relaxed) > 0
+    }
+
+    pub(crate) fn memory_usage(&self) -> usize {
+        let callback = mem::size_of::<(usize, Callback)>();
+        self.wakers.lock().memory_usage()
+            + self.below.lock().capacity() * callback
+            + self.above.lock().capacity() * callback
+    }
+
+    /// Forget everything watching a finished group, so that none of it
+    /// carries over to the next group to use the allocation.
+    pub(crate) fn reset(&self) {
+        self.watchers.store(0, Ordering::SeqCst);
+        self.tracked.store(false, Ordering::Relaxed);
+        self.drops.store(0, Ordering::SeqCst);
+        *self.wakers.lock() = Wakers::default();
+        self.below.lock().clear();
+        self.above.lock().clear();
+    }
+
+    /// Take the wakers of everything waiting on the watch.
+    pub(crate) fn take_wakers(&self) -> Vec<Waker> {
+        self.wakers.lock().take_all().collect()
+    }
+
+    /// Report a count on `shared`'s group that was given up, and whether it was
+    /// a [Ref] being dropped.
+    pub(crate) fn report(&self, shared: &Shared, dropped: bool) {
+        let count = shared.count.lock().strong_count();
+        // The final drop reports itself.
+        if count != 0 {
+            self.released(count, dropped);
+        }
+    }
+
+    /// Report that a [Ref] was dropped, leaving `count` behind.
+    pub(crate) fn dropped(&self, count: usize) {
+        self.released(count, true)
+    }
+
+    /// Report that a count on the group was given up, leaving `count` behind,
+    /// and whether it was a [Ref] being dropped.
+    pub(crate) fn released(&self, count: usize, dropped: bool) {
+        if dropped {
+            self.drops.fetch_add(1, Ordering::SeqCst);
+        }
+        // Same as the final wake: a bad waker mustn't keep the callbacks from
+        // firing.
+        let wakers = self.wakers.lock().take_all();
+        let panic = wake_caught(wakers);
+        self.fire(&self.below, |n| count < n);
+        resume(panic);
+    }
+
+    /// Report that a [Ref] was created, bringing the total to `count`.
+    pub(crate) fn created(&self, count: usize) {
+        wake_all(&self.wakers);
+        self.fire(&self.above, |n| count > n);
+    }
+
+    fn fire(&self, callbacks: &Mutex<Vec<(usize, Callback)>>, crossed: impl Fn(usize) -> bool) {
+        let fired = {
+            let mut callbacks = callbacks.lock();
+            let (fired, waiting): (Vec<_>, _) = callbacks.drain(..).partition(|(n, _)| crossed(*n));
+            *callbacks = waiting;
+            fired
+        };
+
+        for (_, callback) in fired {
+            self.watchers.fetch_sub(1, Ordering::SeqCst);
+            callback();
+        }
+    }
+}
+
+impl Waiter {
+    /// Count a new watcher, before it first checks the count.
+    fn add_watcher(&self) {
+        let watch = &self.shared.watch;
+        watch.watchers.fetch_add(1, Ordering::SeqCst);
+        if !watch.tracked.load(Ordering::Relaxed) {
+            let mut watched = WATCHED.lock();
+            if !watch.tracked.swap(true, Ordering::Relaxed) {
+                // Forget groups that are gone, or were this one before it
+                // was reused.
+                watched.retain(|(_, shared)| {
+                    shared.strong_count() != 0 && shared.as_ptr() != Arc::as_ptr(&self.shared)
+                });
+                watched.push((self.group_id(), Arc::downgrade(&self.shared)));
+            }
+        }
+
+        // A drop that reads the epoch from here on also sees the watcher.
+        EPOCH.fetch_add(1, Ordering::Release);
+        // One that checked before, but gives up its count after this one is
+        // taken, sees the epoch move. One that gave up its count before is
+        // reported along with this one.
+        atomic::fence(Ordering::Release);
+        if let Some(inner) = self.count.upgrade() {
+            inner.release(false);
+        }
+    }
+
+    fn register(&self) -> Registration {
+        let watch = &self.shared.watch;
+        self.add_watcher();
+        let idx = watch.wakers.lock().allocate();
+        Registration {
+            idx,
+            waiter: self.clone(),
+        }
+    }
+
+    /// Wait for the next [Ref] to be dropped
+    ///
+    /// Unlike [Waiter::wait], this resolves as soon as any single [Ref] is
+    /// dropped after the future is created, or immediately if there are no
+    /// [Ref]s left.
+    pub fn wait_any_drop(&self) -> AnyDropFuture {
+        let reg = self.register();
+        let drops = self.shared.watch.drops.load(Ordering::SeqCst);
+        AnyDropFuture { reg, drops }
+    }
+
+    /// Wait for the number of live [Ref]s to drop below `n`
+    pub fn wait_below(&self, n: usize) -> BelowFuture {
+        BelowFuture {
+            reg: self.register(),
+            n,
+        }
+    }
+
+    /// Wait for every [Ref] other than `excluded` to be dropped
+    ///
+    /// For a coordinator that holds on to its own [Ref]s to keep the group
+    /// alive, but still wants to know when everyone else is done. [Ref]s from
+    /// other groups are ignored. The excluded [Ref]s have to stay alive until
+    /// this resolves, since it goes by the count: dropping one of them counts
+    /// the same as somebody else finishing.
+    pub fn wait_excluding<'a>(&self, excluded: impl IntoIterator<Item = &'a Ref>) -> BelowFuture {
+        let mut ids = excluded
+            .into_iter()
+            .filter(|r| Arc::as_ptr(r.inner()) == self.count.as_ptr())
+            .map(Ref::id)
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+        self.wait_below(ids.len() + 1)
+    }
+
+    /// Call `callback` once the number of live [Ref]s drops below `n`
+    ///
+    /// The callback runs at most once, on the thread that dropped the [Ref]
+    /// that took it under the threshold. If the count is already below `n`,
+    /// it's called immediately.
+    pub fn on_below(&self, n: usize, callback: impl FnOnce() + Send + 'static) {
+        let watch = &self.shared.watch;
+        // Push the callback before counting the watcher, so that any drop that
+        // sees the watcher finds the callback too. Check after registering, so
+        // that a drop can't slip in between the check and the registration.
+        watch.below.lock().push((n, Box::new(callback)));
+        self.add_watcher();
+        let count = self.count.strong_count();
+        watch.fire(&watch.below, |n| count < n);
+    }
+
+    /// Wait for the number of live [Ref]s to rise above `n`
+    pub fn wait_above(&self, n: usize) -> AboveFuture {
+        AboveFuture {
+            reg: self.register(),
+            n,
+        }
+    }
+
+    /// Call `callback` once the number of live [Ref]s rises above `n`
+    ///
+    /// The callback runs at most once, on the thread that created the [Ref]
+    /// that took it over the threshold. If the count is already above `n`, it's
+    /// called immediately.
+    pub fn on_above(&self, n: usize, callback: impl FnOnce() + Send + 'static) {
+        let watch = &self.shared.watch;
+        watch.above.lock().push((n, Box::new(callback)));
+        self.add_watcher();
+        let count = self.count.strong_count();
+        watch.fire(&watch.above, |n| count > n);
+    }
+
+    /// Wait for room under the group's [quota](Waiter::set_quota) and create a
+    /// new [Ref] in it
+    ///
+    /// Resolves to `None` if every [Ref] has been dropped, since the group
+    /// can't be revived at that point, or once the group has been
+    /// [force-released](Waiter::force_release).
+    pub fn acquire(&self) -> AcquireFuture {
+        AcquireFuture {
+            reg: self.register(),
+        }
+    }
+}
+
+/// A waker slot in a group's [Watch].
+struct Registration {
+    idx: DefaultKey,
+    waiter: Waiter,
+}
+
+impl Registration {
+    fn watch(&self, cx: &mut Context<'_>) -> &Watch {
+        let watch = &self.waiter.shared.watch;
+        watch.wakers.lock().insert(self.idx, cx.waker().clone());
+        watch
+    }
+
+    fn count(&self) -> usize {
+        self.waiter.count.strong_count()
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let watch = &self.waiter.shared.watch;
+        watch.wakers.lock().remove(self.idx);
+        watch.watchers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The future returned from [Waiter::wait_any_drop]
+///
+/// Resolves when any connected [Ref] is dropped.
+pub struct AnyDropFuture {
+    reg: Registration,
+    drops: usize,
+}
+
+impl Future for AnyDropFuture {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let watch = self.reg.watch(cx);
+        if watch.drops.load(Ordering::SeqCst) != self.drops || self.reg.count() == 0 {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// The future returned from [Waiter::wait_below]
+///
+/// Resolves when fewer than the requested number of [Ref]s are alive.
+pub struct BelowFuture {
+    reg: Registration,
+    n: usize,
+}
+
+impl Future for BelowFuture {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.reg.watch(cx);
+        if self.reg.count() < self.n {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// The future returned from [Waiter::wait_above]
+///
+/// Resolves when more than the requested number of [Ref]s are alive.
+pub struct AboveFuture {
+    reg: Registration,
+    n: usize,
+}
+
+impl Future for AboveFuture {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.reg.watch(cx);
+        if self.reg.count() > self.n {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// The future returned from [Waiter::acquire]
+///
+/// Resolves to a new [Ref] once the group has room for it.
+pub struct AcquireFuture {
+    reg: Registration,
+}
+
+impl Future for AcquireFuture {
+    type Output = Option<Ref>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.reg.watch(cx);
+        let waiter = &self.reg.waiter;
+        if waiter.shared.released.load(Ordering::Acquire) {
+            return Poll::Ready(None);
+        }
+        // Giving back a count that wasn't admitted wakes everything on the
+        // watch, this future included, so don't take one while the group is
+        // plainly full.
+        let count = self.reg.count();
+        if count != 0 && !waiter.shared.admits(count) {
+            return Poll::Pending;
+        }
+        let inner = match waiter.count.upgrade() {
+            Some(inner) => inner,
+            None => return Poll::Ready(None),
+        };
+        match Ref::admitted(inner) {
+            Some(r) => Poll::Ready(Some(r)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{
+                AtomicBool,
+                AtomicUsize,
+                Ordering,
+            },
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use futures::{
+        executor::block_on,
+        FutureExt,
+    };
+
+    #[test]
+    fn wait_excluding() {
+        let (task, wait) = crate::awaitdrop();
+        let mine = task.clone();
+        let (other_group, _other_wait) = crate::awaitdrop();
+        let worker = task.clone();
+
+        let fut = wait.wait_excluding([&task, &mine, &task, &other_group]);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(worker);
+        });
+        block_on(fut);
+        assert_eq!(wait.observer().count(), 2);
+    }
+
+    #[test]
+    fn wait_any_drop() {
+        let (task, wait) = crate::awaitdrop();
+        let other = task.clone();
+
+        let fut = wait.wait_any_drop();
+        let rest = wait.wait();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(other);
+        });
+
+        block_on(fut);
+        assert!(rest.now_or_never().is_none());
+
+        drop(task);
+        block_on(wait.wait_any_drop());
+    }
+
+    #[test]
+    fn below() {
+        let (task, wait) = crate::awaitdrop();
+        let mut refs = (0..4).map(|_| task.clone()).collect::<Vec<_>>();
+
+        let fired = Arc::new(AtomicBool::new(false));
+        wait.on_below(3, {
+            let fired = fired.clone();
+            move || fired.store(true, Ordering::SeqCst)
+        });
+        let fut = wait.wait_below(2);
+
+        refs.truncate(2);
+        assert!(!fired.load(Ordering::SeqCst));
+        refs.pop();
+        assert!(fired.load(Ordering::SeqCst));
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(refs);
+        });
+
+        block_on(fut);
+        assert!(wait.wait().now_or_never().is_none());
+        drop(task);
+    }
+
+    #[test]
+    fn below_racing_drops() {
+        for _ in 0..100 {
+            let (task, wait) = crate::awaitdrop();
+            let refs = (0..8).map(|_| task.clone()).collect::<Vec<_>>();
+            drop(task);
+
+            let dropper = thread::spawn(move || {
+                for r in refs {
+                    drop(r);
+                }
+            });
+            // Every callback has to fire once its threshold is crossed, however
+            // the registration lines up with the drops.
+            let fired = Arc::new(AtomicUsize::new(0));
+            for n in 1..=8 {
+                let fired = fired.clone();
+                wait.on_below(n, move || {
+                    fired.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+            dropper.join().unwrap();
+            assert_eq!(fired.load(Ordering::SeqCst), 8);
+        }
+    }
+
+    #[test]
+    fn below_with_failed_upgrades() {
+        for _ in 0..100 {
+            let (task, wait) = crate::awaitdrop();
+            wait.set_quota(Some(1));
+            let other = task.clone();
+            let weak = task.downgrade();
+
+            let fired = Arc::new(AtomicBool::new(false));
+            wait.on_below(2, {
+                let fired = fired.clone();
+                move || fired.store(true, Ordering::SeqCst)
+            });
+            // Upgrades over the quota briefly take a count before giving it
+            // back, which mustn't hide the drop from the watch.
+            let upgrader = thread::spawn(move || {
+                for _ in 0..100 {
+                    assert!(weak.upgrade().is_none());
+                }
+            });
+            drop(other);
+            upgrader.join().unwrap();
+            assert!(fired.load(Ordering::SeqCst));
+            drop(task);
+        }
+    }
+
+    #[test]
+    fn acquire() {
+        let (task, wait) = crate::awaitdrop();
+        wait.set_quota(Some(1));
+
+        let fut = wait.acquire();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(task);
+        });
+
+        // The only Ref is dropped, which also means the group is done.
+        assert!(block_on(fut).is_none());
+
+        let (task, wait) = crate::awaitdrop();
+        wait.set_quota(Some(2));
+        let second = block_on(wait.acquire()).unwrap();
+        let third = wait.acquire();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(second);
+        });
+
+        let third = block_on(third).unwrap();
+        drop((task, third));
+        block_on(wait.wait());
+    }
+
+    #[test]
+    fn above() {
+        let (task, wait) = crate::awaitdrop();
+
+        let fired = Arc::new(AtomicBool::new(false));
+        wait.on_above(2, {
+            let fired = fired.clone();
+            move || fired.store(true, Ordering::SeqCst)
+        });
+        let fut = wait.wait_above(3);
+
+        let mut refs = vec![task.clone()];
+        assert!(!fired.load(Ordering::SeqCst));
+        refs.push(task.downgrade().upgrade().unwrap());
+        assert!(fired.load(Ordering::SeqCst));
+
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            refs.push(task.clone());
+            (task, refs)
+        });
+
+        block_on(fut);
+        drop(handle.join().unwrap());
+        block_on(wait.wait());
+    }
+}